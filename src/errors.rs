@@ -77,6 +77,10 @@ impl ResponseError for Error {
                 let error: ErrorResponse = message.into();
                 HttpResponse::NotFound().json(error)
             }
+            Error::Unauthorized => {
+                let error: ErrorResponse = "Unauthorized".into();
+                HttpResponse::Unauthorized().json(error)
+            }
             Error::Forbidden => {
                 let error: ErrorResponse = "Forbidden".into();
                 HttpResponse::Forbidden().json(error)
@@ -0,0 +1,158 @@
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::ServiceResponse,
+    http::header,
+    web,
+    HttpRequest,
+};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::schema;
+use crate::diesel::{
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use crate::utils::establish_connection;
+
+pub const DEFAULT_LOCALE: &str = "en";
+pub const LOCALES: &[&str] = &["en", "ru"];
+
+/// Catalogs are keyed by the English message, so a message without a
+/// translation falls back to itself.
+fn catalogs() -> &'static HashMap<&'static str, HashMap<String, String>> {
+    static CATALOGS: OnceLock<HashMap<&'static str, HashMap<String, String>>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        let mut catalogs = HashMap::new();
+        for (locale, source) in [
+            ("en", include_str!("../../locales/en.json")),
+            ("ru", include_str!("../../locales/ru.json")),
+        ] {
+            catalogs.insert(locale, serde_json::from_str(source).expect("Locale catalogs must be valid JSON"));
+        }
+        catalogs
+    })
+}
+
+/// `ru-RU` -> `ru`; None for locales we have no catalog for.
+pub fn normalize(tag: &str) -> Option<&'static str> {
+    let primary = tag.trim().split(|c| c == '-' || c == '_').next()?.to_lowercase();
+    LOCALES.iter().find(|l| **l == primary).copied()
+}
+
+/// Picks the supported locale with the highest `q` from an
+/// `Accept-Language` header.
+pub fn negotiate(accept_language: &str) -> &'static str {
+    let mut best: Option<(&'static str, f32)> = None;
+    for part in accept_language.split(',') {
+        let mut pieces = part.split(';');
+        let tag = pieces.next().unwrap_or("");
+        let q = pieces
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if let Some(locale) = normalize(tag) {
+            if q > 0.0 && best.map_or(true, |(_, best_q)| q > best_q) {
+                best = Some((locale, q));
+            }
+        }
+    }
+    best.map(|(locale, _)| locale).unwrap_or(DEFAULT_LOCALE)
+}
+
+fn user_locale(uuid: &str) -> Option<String> {
+    let _connection = establish_connection();
+    schema::users::table
+        .filter(schema::users::uuid.eq(uuid))
+        .select(schema::users::locale)
+        .first::<String>(&_connection)
+        .ok()
+}
+
+/// The signed-in user's saved locale, otherwise `Accept-Language`. The
+/// user is looked up on the blocking pool.
+pub async fn request_locale(req: &HttpRequest) -> String {
+    let secret = req.headers().get("secret").and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    if let Some(uuid) = secret {
+        if let Ok(Some(locale)) = web::block(move || user_locale(&uuid)).await {
+            return locale;
+        }
+    }
+    let accept_language = req.headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    negotiate(accept_language).to_string()
+}
+
+/// Translates `message`. Messages with a variable tail such as
+/// "Unknown segment: foo" are matched by their longest known prefix.
+pub fn t(locale: &str, message: &str) -> String {
+    let catalog = match catalogs().get(locale) {
+        Some(catalog) => catalog,
+        None => return message.to_string(),
+    };
+    if let Some(text) = catalog.get(message) {
+        return text.clone();
+    }
+    catalog.iter()
+        .filter(|(key, _)| {
+            message.starts_with(key.as_str())
+                && message[key.len()..].starts_with(|c: char| c == ' ' || c == ':')
+        })
+        .max_by_key(|(key, _)| key.len())
+        .map(|(key, text)| text.clone() + &message[key.len()..])
+        .unwrap_or_else(|| message.to_string())
+}
+
+fn translate_value(locale: &str, value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(message) => *message = t(locale, message),
+        serde_json::Value::Array(items) => {
+            for i in items.iter_mut() {
+                translate_value(locale, i);
+            }
+        },
+        serde_json::Value::Object(map) => {
+            for key in ["errors", "message"] {
+                if let Some(i) = map.get_mut(key) {
+                    translate_value(locale, i);
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Response middleware: translates the messages of JSON error responses
+/// (`Error`, `ApiError` and `AuthError` bodies) into the request locale.
+pub async fn translate_errors<B>(res: ServiceResponse<B>) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+where
+    B: MessageBody + 'static,
+{
+    let is_error = res.status().is_client_error() || res.status().is_server_error();
+    let is_json = res.headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(false, |v| v.as_bytes().starts_with(b"application/json"));
+    if !is_error || !is_json {
+        return Ok(res.map_into_boxed_body());
+    }
+    let locale = request_locale(&res.request().clone()).await;
+    if locale == DEFAULT_LOCALE {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Internal Server Error"))?;
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(mut value) => {
+            translate_value(&locale, &mut value);
+            BoxBody::new(serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec()))
+        },
+        Err(_) => BoxBody::new(bytes),
+    };
+    Ok(ServiceResponse::new(req, res.set_body(body)))
+}
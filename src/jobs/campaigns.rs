@@ -0,0 +1,34 @@
+use std::time::Duration;
+use actix_web::{rt, web};
+use crate::models::Campaign;
+
+const CAMPAIGN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Moves due campaigns into the email outbox.
+pub async fn dispatch_campaigns() {
+    let mut interval = rt::time::interval(CAMPAIGN_INTERVAL);
+    loop {
+        interval.tick().await;
+        let campaigns = match web::block(|| Campaign::take_due()).await {
+            Ok(campaigns) => campaigns,
+            Err(err) => {
+                println!("campaigns error: {}", err);
+                continue;
+            },
+        };
+        for campaign in campaigns.into_iter() {
+            let result = web::block(move || {
+                let result = campaign.dispatch();
+                if result.is_err() {
+                    campaign.reschedule();
+                }
+                result.map(|count| (campaign.id, count))
+            }).await;
+            match result {
+                Ok(Ok((id, count))) => println!("campaign {} queued for {} recipients", id, count),
+                Ok(Err(err)) => println!("campaign dispatch error: {}", err),
+                Err(err) => println!("campaigns error: {}", err),
+            }
+        }
+    }
+}
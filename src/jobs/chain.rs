@@ -0,0 +1,384 @@
+use std::time::Duration;
+use actix_web::{rt, web};
+use serde_json::{json, Value};
+use crate::models::{ChainCursor, ChainEvent, NewChainEvent};
+use crate::utils::{
+    address_topic,
+    event_topic,
+    hex_to_decimal,
+    parse_quantity,
+    topic_address,
+    RpcClient,
+    RpcLog,
+    WalletAddress,
+};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(15);
+/// Largest block range asked from the node in one `eth_getLogs` call.
+const MAX_RANGE: u64 = 2000;
+const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+struct WatchedContract {
+    address: String,
+    kind:    &'static str,
+    topics:  Value,
+}
+
+/// What one pass over a contract found.
+enum Step {
+    /// nothing new is deep enough yet
+    Idle,
+    /// the last scanned block is no longer on the chain
+    Rewind(i64),
+    Store {
+        events:       Vec<NewChainEvent>,
+        block_number: i64,
+        block_hash:   String,
+    },
+}
+
+/// Follows the token and sale contracts, and transfers of the payment
+/// token to the payment address, over JSON-RPC. Blocks are only
+/// scanned once they are `CHAIN_CONFIRMATIONS` deep, and the hash of the
+/// last scanned block is checked on every pass so a deeper reorg rewinds
+/// the cursor instead of leaving stale events behind.
+pub async fn watch_chain() {
+    let client = RpcClient::new(crate::vars::rpc_url(), crate::vars::rpc_key());
+    let mut contracts = Vec::new();
+    if let Some(address) = crate::vars::token_contract() {
+        contracts.push(WatchedContract {
+            address: address.to_lowercase(),
+            kind:    "transfer",
+            topics:  json!([event_topic(TRANSFER_EVENT)]),
+        });
+    }
+    if let Some(address) = crate::vars::sale_contract() {
+        contracts.push(WatchedContract {
+            address: address.to_lowercase(),
+            kind:    "purchase",
+            topics:  json!([event_topic(&crate::vars::purchase_event())]),
+        });
+    }
+    if let Some(address) = crate::vars::payment_token_contract() {
+        let to = match WalletAddress::parse(&crate::vars::payment_address().unwrap_or_default()) {
+            Ok(to) => to,
+            Err(err) => {
+                println!("chain watcher: PAYMENT_ADDRESS is invalid: {}", err);
+                return;
+            },
+        };
+        contracts.push(WatchedContract {
+            address: address.to_lowercase(),
+            kind:    "payment",
+            topics:  json!([event_topic(TRANSFER_EVENT), null, address_topic(&to)]),
+        });
+    }
+
+    let mut interval = rt::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        for contract in contracts.iter() {
+            if let Err(err) = scan(&client, contract).await {
+                println!("chain watcher error ({}): {}", contract.address, err);
+            }
+        }
+    }
+}
+
+async fn scan(client: &RpcClient, contract: &WatchedContract) -> Result<(), String> {
+    let address = contract.address.clone();
+    let cursor = web::block(move || ChainCursor::get_or_create(&address, crate::vars::chain_start_block()))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match next_step(client, contract, &cursor, crate::vars::chain_confirmations()).await? {
+        Step::Idle => {},
+        Step::Rewind(rewind_to) => {
+            println!("chain reorg at block {}, rewinding to {}", cursor.block_number, rewind_to);
+            web::block(move || cursor.rewind(rewind_to))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+        },
+        Step::Store { events, block_number, block_hash } => {
+            let stored = web::block(move || ChainEvent::store(&cursor, events, block_number, block_hash))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+            if stored > 0 {
+                println!("chain events stored ({}): {}", contract.address, stored);
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Decides what to do after `cursor`: rewind if its block was replaced,
+/// otherwise fetch the logs of the next range that is `confirmations` deep.
+async fn next_step(client: &RpcClient, contract: &WatchedContract, cursor: &ChainCursor, confirmations: u64) -> Result<Step, String> {
+    let head = client.block_number().await?;
+    let safe = head.saturating_sub(confirmations);
+
+    if let Some(hash) = cursor.block_hash.as_ref() {
+        let block = client.get_block(cursor.block_number as u64).await?;
+        if block.map_or(true, |b| &b.hash != hash) {
+            return Ok(Step::Rewind((cursor.block_number - confirmations as i64).max(0)));
+        }
+    }
+
+    let from = cursor.block_number as u64 + 1;
+    if from > safe {
+        return Ok(Step::Idle);
+    }
+    let to = safe.min(from + MAX_RANGE - 1);
+
+    let logs = client.get_logs(&contract.address, &contract.topics, from, to).await?;
+    let mut events = Vec::new();
+    for log in logs.iter().filter(|l| !l.removed) {
+        // a log we cannot read must not hold back the rest of the range
+        match decode(contract.kind, log) {
+            Ok(event) => events.push(event),
+            Err(err) => println!("chain watcher skipped a log ({}): {}", contract.address, err),
+        }
+    }
+    let block_hash = client.get_block(to).await?
+        .ok_or_else(|| "block ".to_string() + &to.to_string() + " not found")?
+        .hash;
+    Ok(Step::Store {
+        events:       events,
+        block_number: to as i64,
+        block_hash:   block_hash,
+    })
+}
+
+fn decode(kind: &str, log: &RpcLog) -> Result<NewChainEvent, String> {
+    let data = log.data.trim_start_matches("0x");
+    if data.len() < 64 {
+        return Err("unexpected log data in ".to_string() + &log.transaction_hash);
+    }
+    let (from_address, to_address) = match kind {
+        "transfer" | "payment" => {
+            if log.topics.len() < 3 {
+                return Err("unexpected Transfer topics in ".to_string() + &log.transaction_hash);
+            }
+            (topic_address(&log.topics[1])?, topic_address(&log.topics[2])?)
+        },
+        _ => {
+            if log.topics.len() < 2 {
+                return Err("unexpected purchase topics in ".to_string() + &log.transaction_hash);
+            }
+            // purchases mint to the buyer, which is its first indexed argument
+            (WalletAddress::parse(ZERO_ADDRESS)?, topic_address(&log.topics[1])?)
+        },
+    };
+    Ok(NewChainEvent {
+        contract:     log.address.to_lowercase(),
+        kind:         kind.to_string(),
+        block_number: parse_quantity(&log.block_number)? as i64,
+        block_hash:   log.block_hash.to_lowercase(),
+        tx_hash:      log.transaction_hash.to_lowercase(),
+        log_index:    parse_quantity(&log.log_index)? as i32,
+        from_address: from_address.to_string(),
+        to_address:   to_address.to_string(),
+        amount:       hex_to_decimal(&data[..64])?,
+        created:      chrono::Utc::now().naive_utc(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::to_quantity;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    const TOKEN: &str = "0x00000000000000000000000000000000000000aa";
+    const HOLDER: &str = "0x000000000000000000000000000000000000000000000000000000000000beef";
+
+    /// The chain as the mock node reports it.
+    #[derive(Default)]
+    struct Chain {
+        head:   u64,
+        /// overrides of `block_hash` for blocks that were reorganised
+        hashes: HashMap<u64, String>,
+        /// block numbers with one token transfer each
+        logs:   Vec<u64>,
+        /// block numbers with one transfer log that has no amount
+        bad:    Vec<u64>,
+    }
+
+    fn block_hash(chain: &Chain, number: u64) -> String {
+        chain.hashes.get(&number).cloned().unwrap_or_else(|| format!("0x{:064x}", number))
+    }
+
+    fn answer(chain: &Chain, method: &str, params: &Value) -> Value {
+        match method {
+            "eth_blockNumber" => json!(to_quantity(chain.head)),
+            "eth_getBlockByNumber" => {
+                let number = parse_quantity(params[0].as_str().unwrap()).unwrap();
+                match number <= chain.head {
+                    true => json!({ "number": to_quantity(number), "hash": block_hash(chain, number) }),
+                    false => Value::Null,
+                }
+            },
+            "eth_getLogs" => {
+                let from = parse_quantity(params[0]["fromBlock"].as_str().unwrap()).unwrap();
+                let to = parse_quantity(params[0]["toBlock"].as_str().unwrap()).unwrap();
+                let logs = chain.logs.iter().map(|n| (*n, format!("0x{:064x}", 1000)))
+                    .chain(chain.bad.iter().map(|n| (*n, "0x".to_string())));
+                Value::from(logs
+                    .filter(|(n, _)| *n >= from && *n <= to)
+                    .map(|(n, data)| json!({
+                        "address":         TOKEN,
+                        "topics":          [event_topic(TRANSFER_EVENT), HOLDER, HOLDER],
+                        "data":            data,
+                        "blockNumber":     to_quantity(n),
+                        "blockHash":       block_hash(chain, n),
+                        "transactionHash": format!("0x{:064x}", n + 1_000_000),
+                        "logIndex":        "0x0",
+                    }))
+                    .collect::<Vec<Value>>())
+            },
+            _ => panic!("unexpected method {}", method),
+        }
+    }
+
+    /// A JSON-RPC node on a local port answering from `chain`.
+    fn mock_node(chain: Arc<Mutex<Chain>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse::<usize>().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let result = answer(&chain.lock().unwrap(), request["method"].as_str().unwrap(), &request["params"]);
+                let body = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body,
+                ).unwrap();
+            }
+        });
+        url
+    }
+
+    fn contract() -> WatchedContract {
+        WatchedContract {
+            address: TOKEN.to_string(),
+            kind:    "transfer",
+            topics:  json!([event_topic(TRANSFER_EVENT)]),
+        }
+    }
+
+    fn cursor(block_number: i64, block_hash: Option<String>) -> ChainCursor {
+        ChainCursor {
+            contract:     TOKEN.to_string(),
+            block_number: block_number,
+            block_hash:   block_hash,
+        }
+    }
+
+    fn setup(chain: Chain) -> (Arc<Mutex<Chain>>, RpcClient) {
+        let chain = Arc::new(Mutex::new(chain));
+        let client = RpcClient::new(mock_node(chain.clone()), String::new());
+        (chain, client)
+    }
+
+    #[actix_web::test]
+    async fn waits_for_confirmations() {
+        let (chain, client) = setup(Chain { head: 100, logs: vec![89], ..Chain::default() });
+        let cursor = cursor(88, Some(format!("0x{:064x}", 88)));
+
+        let step = next_step(&client, &contract(), &cursor, 12).await.unwrap();
+        assert!(matches!(step, Step::Idle));
+
+        chain.lock().unwrap().head = 101;
+        match next_step(&client, &contract(), &cursor, 12).await.unwrap() {
+            Step::Store { events, block_number, block_hash } => {
+                assert_eq!(block_number, 89);
+                assert_eq!(block_hash, format!("0x{:064x}", 89));
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0].block_number, 89);
+                assert_eq!(events[0].amount, "1000");
+            },
+            _ => panic!("expected the block to be stored"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn stores_only_deep_enough_logs() {
+        let (_chain, client) = setup(Chain { head: 100, logs: vec![85, 95], ..Chain::default() });
+        match next_step(&client, &contract(), &cursor(80, None), 12).await.unwrap() {
+            Step::Store { events, block_number, .. } => {
+                assert_eq!(block_number, 88);
+                assert_eq!(events.iter().map(|i| i.block_number).collect::<Vec<i64>>(), vec![85]);
+            },
+            _ => panic!("expected blocks 81..=88 to be stored"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn skips_undecodable_logs() {
+        let (_chain, client) = setup(Chain { head: 100, logs: vec![85], bad: vec![86], ..Chain::default() });
+        match next_step(&client, &contract(), &cursor(80, None), 12).await.unwrap() {
+            Step::Store { events, block_number, .. } => {
+                assert_eq!(block_number, 88);
+                assert_eq!(events.iter().map(|i| i.block_number).collect::<Vec<i64>>(), vec![85]);
+            },
+            _ => panic!("expected blocks 81..=88 to be stored"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn rewinds_on_reorg() {
+        let (chain, client) = setup(Chain { head: 100, ..Chain::default() });
+        let cursor = cursor(80, Some(format!("0x{:064x}", 80)));
+        assert!(matches!(next_step(&client, &contract(), &cursor, 12).await.unwrap(), Step::Store { .. }));
+
+        chain.lock().unwrap().hashes.insert(80, format!("0x{:064x}", 1));
+        match next_step(&client, &contract(), &cursor, 12).await.unwrap() {
+            Step::Rewind(block_number) => assert_eq!(block_number, 68),
+            _ => panic!("expected a rewind"),
+        }
+    }
+
+    #[test]
+    fn decodes_payment_transfer() {
+        let log = RpcLog {
+            address:          TOKEN.to_uppercase().replace("0X", "0x"),
+            topics:           vec![event_topic(TRANSFER_EVENT), HOLDER.to_string(), HOLDER.to_string()],
+            data:             format!("0x{:064x}", 2_500_000),
+            block_number:     "0x10".to_string(),
+            block_hash:       format!("0x{:064x}", 16),
+            transaction_hash: format!("0x{:064X}", 7),
+            log_index:        "0x2".to_string(),
+            removed:          false,
+        };
+        let event = decode("payment", &log).unwrap();
+        assert_eq!(event.contract, TOKEN);
+        assert_eq!(event.to_address, "0x000000000000000000000000000000000000bEEF");
+        assert_eq!(event.amount, "2500000");
+        assert_eq!(event.block_number, 16);
+        assert_eq!(event.log_index, 2);
+        assert_eq!(event.tx_hash, format!("0x{:064x}", 7));
+    }
+}
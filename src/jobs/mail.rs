@@ -0,0 +1,34 @@
+use std::time::Duration;
+use actix_web::{rt, web};
+use crate::models::EmailOutbox;
+use crate::mailer;
+
+const OUTBOX_INTERVAL: Duration = Duration::from_secs(10);
+const OUTBOX_BATCH: i64 = 50;
+
+/// Delivers due messages from `email_outbox` through the configured
+/// transport, rescheduling failures with backoff. Addresses suppressed
+/// since a message was queued are skipped.
+pub async fn deliver_outbox() {
+    let mut interval = rt::time::interval(OUTBOX_INTERVAL);
+    loop {
+        interval.tick().await;
+        let items = match web::block(|| EmailOutbox::skip_suppressed(EmailOutbox::take_due(OUTBOX_BATCH))).await {
+            Ok(items) => items,
+            Err(err) => {
+                println!("email outbox error: {}", err);
+                continue;
+            },
+        };
+        for item in items.into_iter() {
+            let result = mailer::deliver(&item.get_mail()).await;
+            let done = web::block(move || match result {
+                Ok(()) => item.mark_sent(),
+                Err(err) => item.mark_failed(err.to_string()),
+            }).await;
+            if let Err(err) = done {
+                println!("email outbox error: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+mod payments;
+mod white_list;
+mod chain;
+mod mail;
+mod campaigns;
+
+pub use self::{
+    payments::*,
+    white_list::*,
+    chain::*,
+    mail::*,
+    campaigns::*,
+};
+
+/// Spawns the periodic background jobs on the actix runtime.
+pub fn start() {
+    actix_web::rt::spawn(expire_payments());
+    actix_web::rt::spawn(reconcile_white_lists());
+    actix_web::rt::spawn(deliver_outbox());
+    actix_web::rt::spawn(dispatch_campaigns());
+    if crate::vars::token_contract().is_some()
+        || crate::vars::sale_contract().is_some()
+        || crate::vars::payment_token_contract().is_some()
+    {
+        actix_web::rt::spawn(watch_chain());
+    }
+}
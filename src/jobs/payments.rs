@@ -0,0 +1,18 @@
+use std::time::Duration;
+use actix_web::{rt, web};
+use crate::models::Payment;
+
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Marks approvals that were not paid before `expires` as expired.
+pub async fn expire_payments() {
+    let mut interval = rt::time::interval(EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match web::block(|| Payment::expire_overdue()).await {
+            Ok(count) if count > 0 => println!("expired payments: {}", count),
+            Ok(_) => {}
+            Err(err) => println!("expire payments error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+use actix_web::{rt, web};
+use crate::models::WhiteListMismatch;
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Finds approved applications and white list rows that disagree and
+/// stores them for admins, see `/get_white_list_mismatches/`.
+pub async fn reconcile_white_lists() {
+    let mut interval = rt::time::interval(RECONCILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let res = web::block(|| {
+            let list = WhiteListMismatch::find_all();
+            WhiteListMismatch::store(&list).map(|_| list.len())
+        }).await;
+        match res {
+            Ok(Ok(count)) if count > 0 => println!("white list mismatches: {}", count),
+            Ok(Ok(_)) => {},
+            Ok(Err(err)) => println!("reconcile white lists error: {}", err),
+            Err(err) => println!("reconcile white lists error: {}", err),
+        }
+    }
+}
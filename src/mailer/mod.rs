@@ -0,0 +1,88 @@
+mod sanitize;
+mod templates;
+mod transport;
+
+pub use self::{
+    sanitize::*,
+    templates::*,
+    transport::*,
+};
+
+use serde::{Deserialize, Serialize};
+use crate::errors::Error;
+use crate::models::{EmailOutbox, NotificationSubscription};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Recipient {
+    pub email:  String,
+    pub name:   String,
+    #[serde(default = "default_locale")]
+    pub locale: String,
+}
+
+fn default_locale() -> String {
+    crate::i18n::DEFAULT_LOCALE.to_string()
+}
+
+impl Recipient {
+    pub fn new(email: &str, name: &str) -> Recipient {
+        Recipient {
+            email:  email.to_string(),
+            name:   name.to_string(),
+            locale: default_locale(),
+        }
+    }
+    /// Templates are rendered in `locale` when they have a translation.
+    pub fn with_locale(mut self, locale: &str) -> Recipient {
+        self.locale = crate::i18n::normalize(locale)
+            .unwrap_or(crate::i18n::DEFAULT_LOCALE)
+            .to_string();
+        self
+    }
+}
+
+/// Renders `template` for `recipient` and sends it.
+pub async fn send<T: Serialize>(template: &str, recipient: &Recipient, context: &T) -> Result<(), Error> {
+    let mail = render_localized(template, &recipient.locale, context)?;
+    deliver(&mail.into_outgoing(recipient)).await
+}
+
+/// Hands an already rendered message to the configured transport.
+pub async fn deliver(mail: &OutgoingMail) -> Result<(), Error> {
+    transport().send(mail).await.map_err(|err| {
+        println!("Error: {}", err);
+        Error::InternalServerError(err)
+    })
+}
+
+/// Renders `template` for `recipient` and puts it in the outbox; the
+/// outbox worker delivers it and retries on failure.
+pub fn queue<T: Serialize>(template: &str, recipient: &Recipient, context: &T) -> Result<EmailOutbox, Error> {
+    queue_with_headers(template, recipient, context, Vec::new())
+}
+
+pub fn queue_with_headers<T: Serialize>(template: &str, recipient: &Recipient, context: &T, headers: Vec<(String, String)>) -> Result<EmailOutbox, Error> {
+    let mut mail = render_localized(template, &recipient.locale, context)?.into_outgoing(recipient);
+    mail.headers = headers;
+    EmailOutbox::create(template, &mail)
+}
+
+/// Queues and only logs a failure, for handlers where mail is a side effect.
+pub fn queue_or_log<T: Serialize>(template: &str, recipient: &Recipient, context: &T) {
+    if let Err(err) = queue(template, recipient, context) {
+        println!("mail {} to {} failed: {}", template, recipient.email, err);
+    }
+}
+
+/// Queues `template` to every admin and outside address subscribed to
+/// `event` by email. The recipient's first name is available to the
+/// template as `name`.
+pub fn notify_admins(event: &str, template: &str, context: serde_json::Value) {
+    for (recipient, first_name) in NotificationSubscription::get_recipients(event, "email").iter() {
+        let mut _context = context.clone();
+        if let Some(map) = _context.as_object_mut() {
+            map.insert("name".to_string(), serde_json::Value::from(first_name.clone()));
+        }
+        queue_or_log(template, recipient, &_context);
+    }
+}
@@ -0,0 +1,44 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+const ALLOWED_TAGS: &[&str] = &[
+    "a", "b", "blockquote", "br", "div", "em", "h1", "h2", "h3", "h4", "hr",
+    "i", "img", "li", "ol", "p", "span", "strong", "table", "tbody", "td",
+    "th", "thead", "tr", "u", "ul",
+];
+
+fn cleaner() -> &'static ammonia::Builder<'static> {
+    static CLEANER: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+    CLEANER.get_or_init(|| {
+        let mut builder = ammonia::Builder::empty();
+        builder
+            .add_tags(ALLOWED_TAGS)
+            .add_tag_attributes("a", &["href", "title"])
+            .add_tag_attributes("img", &["src", "alt", "width", "height"])
+            .add_tag_attributes("td", &["colspan", "rowspan"])
+            .add_tag_attributes("th", &["colspan", "rowspan"])
+            .add_generic_attributes(&["align"])
+            .clean_content_tags(HashSet::from(["script", "style"]))
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .url_relative(ammonia::UrlRelative::Deny)
+            .link_rel(Some("noopener noreferrer"));
+        builder
+    })
+}
+
+/// Strips admin-authored html down to formatting, links and images.
+/// Scripts, styles, event handlers and non-http links are removed.
+pub fn sanitize_html(html: &str) -> String {
+    cleaner().clean(html).to_string()
+}
+
+/// A hex SHA-256 of the subject and body, recorded in the logs for every
+/// admin-authored email.
+pub fn content_hash(subject: &str, html: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(subject.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(html.as_bytes());
+    hex::encode(hasher.finalize())
+}
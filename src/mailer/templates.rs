@@ -0,0 +1,156 @@
+use serde::Serialize;
+use std::sync::OnceLock;
+use tera::{Context, Tera};
+use crate::errors::Error;
+use crate::mailer::{OutgoingMail, Recipient};
+
+macro_rules! email_template {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("../../templates/email/", $name, ".subject.txt")),
+            include_str!(concat!("../../templates/email/", $name, ".html")),
+            include_str!(concat!("../../templates/email/", $name, ".txt")),
+        )
+    };
+    ($locale:literal, $name:literal) => {
+        (
+            concat!($locale, "/", $name),
+            include_str!(concat!("../../templates/email/", $locale, "/", $name, ".subject.txt")),
+            include_str!(concat!("../../templates/email/", $locale, "/", $name, ".html")),
+            include_str!(concat!("../../templates/email/", $locale, "/", $name, ".txt")),
+        )
+    };
+}
+
+/// name, subject, html body, text body
+const TEMPLATES: &[(&str, &str, &str, &str)] = &[
+    email_template!("invite"),
+    email_template!("new_user"),
+    email_template!("new_application"),
+    email_template!("application_submitted"),
+    email_template!("application_approved"),
+    email_template!("application_approved_admin"),
+    email_template!("subscribe"),
+    email_template!("subscribe_confirm"),
+    email_template!("subscribe_monitoring"),
+    email_template!("custom"),
+    email_template!("campaign"),
+];
+
+/// Translations of `TEMPLATES`, named `locale/name`. A template without a
+/// translation is sent in English.
+const LOCALIZED_TEMPLATES: &[(&str, &str, &str, &str)] = &[
+    email_template!("ru", "invite"),
+    email_template!("ru", "application_submitted"),
+    email_template!("ru", "application_approved"),
+    email_template!("ru", "subscribe"),
+    email_template!("ru", "subscribe_confirm"),
+];
+
+/// A template rendered for one recipient.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedMail {
+    pub subject: String,
+    pub html:    String,
+    pub text:    String,
+}
+
+impl RenderedMail {
+    pub fn into_outgoing(self, recipient: &Recipient) -> OutgoingMail {
+        OutgoingMail {
+            from_email: crate::vars::mail_from(),
+            from_name:  crate::vars::mail_from_name(),
+            to:         recipient.clone(),
+            subject:    self.subject,
+            html:       self.html,
+            text:       self.text,
+            headers:    Vec::new(),
+            outbox_id:  None,
+        }
+    }
+}
+
+fn tera() -> &'static Tera {
+    static TERA: OnceLock<Tera> = OnceLock::new();
+    TERA.get_or_init(|| {
+        let mut tera = Tera::default();
+        let mut files = vec![("base.html".to_string(), include_str!("../../templates/email/base.html").to_string())];
+        for (name, subject, html, text) in TEMPLATES.iter().chain(LOCALIZED_TEMPLATES.iter()) {
+            files.push((name.to_string() + ".subject.txt", subject.to_string()));
+            files.push((name.to_string() + ".html", html.to_string()));
+            files.push((name.to_string() + ".txt", text.to_string()));
+        }
+        tera.add_raw_templates(files).expect("Email templates must compile");
+        tera
+    })
+}
+
+pub fn template_names() -> Vec<&'static str> {
+    TEMPLATES.iter().map(|t| t.0).collect()
+}
+
+/// Renders the subject, html and plain-text parts of `template`. The
+/// dashboard url, support address and payment address are always available
+/// to templates; `context` may override them.
+pub fn render<T: Serialize>(template: &str, context: &T) -> Result<RenderedMail, Error> {
+    render_localized(template, crate::i18n::DEFAULT_LOCALE, context)
+}
+
+/// `render` with the `locale` translation of `template` when there is one.
+pub fn render_localized<T: Serialize>(template: &str, locale: &str, context: &T) -> Result<RenderedMail, Error> {
+    if !TEMPLATES.iter().any(|t| t.0 == template) {
+        return Err(Error::NotFound("Unknown email template: ".to_string() + template));
+    }
+    let localized = locale.to_string() + "/" + template;
+    let name = match LOCALIZED_TEMPLATES.iter().any(|t| t.0 == localized) {
+        true => localized.as_str(),
+        false => template,
+    };
+    let mut _context = Context::new();
+    _context.insert("dashboard_url", &crate::vars::dashboard_url());
+    _context.insert("support_email", &crate::vars::support_email());
+    _context.insert("ico_url", &crate::vars::ico_url());
+    _context.extend(
+        Context::from_serialize(context).map_err(|e| Error::BadRequest(e.to_string()))?
+    );
+
+    let render_part = |part: &str| {
+        tera()
+            .render(&(name.to_string() + part), &_context)
+            .map_err(|e| Error::BadRequest(name.to_string() + part + ": " + &e.to_string()))
+    };
+    Ok(RenderedMail {
+        subject: render_part(".subject.txt")?.trim().to_string(),
+        html:    render_part(".html")?,
+        text:    render_part(".txt")?.trim().to_string(),
+    })
+}
+
+/// A rough plain-text version of admin-authored html for the text part.
+pub fn html_to_text(html: &str) -> String {
+    let html = html
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n")
+        .replace("</p>", "\n\n")
+        .replace("</div>", "\n");
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use crate::mailer::Recipient;
+
+/// A fully rendered message, ready to hand to a transport.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutgoingMail {
+    pub from_email: String,
+    pub from_name:  String,
+    pub to:         Recipient,
+    pub subject:    String,
+    pub html:       String,
+    pub text:       String,
+    pub headers:    Vec<(String, String)>,
+    /// set once the message is in `email_outbox`, so delivery events can be
+    /// matched back to it
+    pub outbox_id:  Option<i32>,
+}
+
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), String>;
+    /// Messages kept by the memory transport; empty for the others.
+    fn sent(&self) -> Vec<OutgoingMail> {
+        Vec::new()
+    }
+}
+
+pub struct SendGridTransport {
+    api_key: String,
+}
+
+#[async_trait]
+impl MailTransport for SendGridTransport {
+    fn name(&self) -> &'static str {
+        "sendgrid"
+    }
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), String> {
+        let sg = sendgrid::SGClient::new(self.api_key.clone());
+        let x_smtpapi = match mail.outbox_id {
+            Some(id) => serde_json::json!({ "unique_args": { "outbox_id": id.to_string() } }).to_string(),
+            None => String::new(),
+        };
+        let mut mail_info = sendgrid::Mail::new()
+            .add_to(sendgrid::Destination {
+                address: &mail.to.email,
+                name:    &mail.to.name,
+            })
+            .add_from(&mail.from_email)
+            .add_from_name(&mail.from_name)
+            .add_subject(&mail.subject)
+            .add_html(&mail.html)
+            .add_text(&mail.text);
+        for (key, value) in mail.headers.iter() {
+            mail_info = mail_info.add_header(key.clone(), value);
+        }
+        if !x_smtpapi.is_empty() {
+            mail_info = mail_info.add_x_smtpapi(&x_smtpapi);
+        }
+        sg.send(mail_info).await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub struct SmtpTransport {
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    fn new(host: &str, port: u16, user: Option<String>, password: Option<String>) -> Result<SmtpTransport, String> {
+        let mut builder = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::starttls_relay(host)
+            .map_err(|e| e.to_string())?
+            .port(port);
+        if let (Some(user), Some(password)) = (user, password) {
+            builder = builder.credentials(lettre::transport::smtp::authentication::Credentials::new(user, password));
+        }
+        Ok(SmtpTransport { transport: builder.build() })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), String> {
+        use lettre::message::{header, Mailbox, MultiPart};
+        use lettre::AsyncTransport;
+
+        let from = Mailbox::new(Some(mail.from_name.clone()), mail.from_email.parse().map_err(|e| format!("{}", e))?);
+        let to = Mailbox::new(Some(mail.to.name.clone()), mail.to.email.parse().map_err(|e| format!("{}", e))?);
+        let mut builder = lettre::Message::builder()
+            .from(from)
+            .to(to)
+            .subject(mail.subject.clone());
+        for (key, value) in mail.headers.iter() {
+            builder = builder.raw_header(header::HeaderValue::new(
+                header::HeaderName::new_from_ascii(key.clone()).map_err(|e| e.to_string())?,
+                value.clone(),
+            ));
+        }
+        let message = builder
+            .multipart(MultiPart::alternative_plain_html(mail.text.clone(), mail.html.clone()))
+            .map_err(|e| e.to_string())?;
+        self.transport.send(message).await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Writes every message as a JSON file into `dir`, for local development.
+pub struct FileTransport {
+    dir: String,
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), String> {
+        let dir = self.dir.clone();
+        let path = format!(
+            "{}/{}-{}.json",
+            self.dir,
+            chrono::Utc::now().format("%Y%m%d%H%M%S%f"),
+            mail.to.email.replace(|c: char| !c.is_ascii_alphanumeric() && c != '@' && c != '.', "_"),
+        );
+        let body = serde_json::to_string_pretty(mail).map_err(|e| e.to_string())?;
+        let _path = path.clone();
+        actix_web::web::block(move || {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&_path, body)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+        println!("mail written to {}", path);
+        Ok(())
+    }
+}
+
+/// Keeps messages in memory so tests can assert on what was sent.
+#[derive(Default)]
+pub struct MemoryTransport {
+    sent: Mutex<Vec<OutgoingMail>>,
+}
+
+#[async_trait]
+impl MailTransport for MemoryTransport {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), String> {
+        self.sent.lock().expect("E.").push(mail.clone());
+        Ok(())
+    }
+    fn sent(&self) -> Vec<OutgoingMail> {
+        self.sent.lock().expect("E.").clone()
+    }
+}
+
+fn build_transport(kind: &str) -> Result<Box<dyn MailTransport>, String> {
+    match kind {
+        "sendgrid" => match crate::vars::email_key() {
+            Some(api_key) => Ok(Box::new(SendGridTransport { api_key: api_key })),
+            None => Err("MAIL_TRANSPORT is sendgrid but EMAIL_KEY is not set".to_string()),
+        },
+        "smtp" => match crate::vars::smtp_host() {
+            Some(host) => SmtpTransport::new(&host, crate::vars::smtp_port(), crate::vars::smtp_user(), crate::vars::smtp_password())
+                .map(|transport| Box::new(transport) as Box<dyn MailTransport>)
+                .map_err(|err| "SMTP transport failed: ".to_string() + &err),
+            None => Err("MAIL_TRANSPORT is smtp but SMTP_HOST is not set".to_string()),
+        },
+        "file" => Ok(Box::new(FileTransport { dir: crate::vars::mail_dir() })),
+        "memory" => Ok(Box::new(MemoryTransport::default())),
+        _ => Err("Unknown MAIL_TRANSPORT: ".to_string() + kind),
+    }
+}
+
+static TRANSPORT: OnceLock<Box<dyn MailTransport>> = OnceLock::new();
+
+/// Sets up the transport chosen by `MAIL_TRANSPORT` (sendgrid, smtp, file
+/// or memory). Called once at startup, so a misconfigured transport stops
+/// the app instead of dropping mail; file and memory are only used when
+/// asked for.
+pub fn init_transport() -> Result<&'static dyn MailTransport, String> {
+    let transport = build_transport(&crate::vars::mail_transport())?;
+    println!("mail transport: {}", transport.name());
+    Ok(TRANSPORT.get_or_init(|| transport).as_ref())
+}
+
+pub fn transport() -> &'static dyn MailTransport {
+    TRANSPORT.get().expect("mail transport is not initialised").as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mail(email: &str) -> OutgoingMail {
+        crate::mailer::render_localized("invite", "ru", &serde_json::json!({ "code": "123456" }))
+            .unwrap()
+            .into_outgoing(&Recipient::new(email, "Test User").with_locale("ru"))
+    }
+
+    #[actix_web::test]
+    async fn memory_transport_keeps_messages() {
+        let transport = build_transport("memory").unwrap();
+        assert_eq!(transport.name(), "memory");
+        transport.send(&mail("first@example.com")).await.unwrap();
+        transport.send(&mail("second@example.com")).await.unwrap();
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].to.email, "first@example.com");
+        assert_eq!(sent[1].to.email, "second@example.com");
+        assert_eq!(sent[0].subject, "Подтверждение почты");
+        assert!(sent[0].html.contains("123456"));
+        assert!(sent[0].text.contains("123456"));
+    }
+
+    #[actix_web::test]
+    async fn file_transport_writes_json() {
+        let dir = std::env::temp_dir().join(format!("mail-test-{}", std::process::id()));
+        let transport = FileTransport { dir: dir.to_string_lossy().to_string() };
+        transport.send(&mail("file@example.com")).await.unwrap();
+
+        let files: Vec<std::path::PathBuf> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&files[0]).unwrap()).unwrap();
+        assert_eq!(written["to"]["email"], "file@example.com");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_transport_is_refused() {
+        assert!(build_transport("carrier-pigeon").is_err());
+    }
+}
@@ -8,12 +8,18 @@ pub mod routes;
 mod errors;
 mod api_error;
 mod vars;
+mod jobs;
+mod mailer;
+mod i18n;
+mod websocket;
  
 use actix_web::{
     HttpServer,
     App,
     middleware::Compress,
     cookie::Key,
+    web,
+    dev::Service,
 };
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
 use crate::routes::routes;
@@ -28,6 +34,15 @@ mod views;
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     let secret_key = Key::generate();
+    mailer::init_transport()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    // buyers are told to pay there, so there is no safe default
+    vars::payment_address()
+        .ok_or_else(|| "PAYMENT_ADDRESS must be set".to_string())
+        .and_then(|address| utils::WalletAddress::parse(&address).map(|_| ()))
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, "PAYMENT_ADDRESS: ".to_string() + &err))?;
+    jobs::start();
+    let server = websocket::start();
 
     HttpServer::new(move || {
         let cors = Cors::default() 
@@ -37,14 +52,24 @@ async fn main() -> std::io::Result<()> {
             })
             .allowed_methods(vec!["GET", "POST"])
             .max_age(3600);
+        // the last `wrap` is the outermost: errors are translated before
+        // the body is compressed
         App::new() 
-            .wrap(Compress::default())
             .wrap(cors)
+            .wrap_fn(|req, srv| {
+                let fut = srv.call(req);
+                async move { i18n::translate_errors(fut.await?).await }
+            })
             .wrap(
                 SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
                     .cookie_secure(false)
                     .build(),
             )
+            .wrap(Compress::default())
+            .app_data(web::Data::new(server.clone()))
+            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+                errors::Error::BadRequest(err.to_string()).into()
+            }))
             .configure(routes)
     })
     .bind("69.167.186.207:9330")?
@@ -0,0 +1,423 @@
+use crate::schema;
+use crate::schema::{
+    campaigns,
+    campaign_recipients,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    BoolExpressionMethods,
+    RunQueryDsl,
+    JoinOnDsl,
+    Connection,
+    PgConnection,
+};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use crate::utils::{establish_connection, get_limit};
+use crate::errors::Error;
+use crate::mailer::{self, Recipient};
+use crate::models::{EmailOutbox, Subscriber, SUBSCRIBER_CONFIRMED};
+
+/*
+status
+
+0. scheduled
+1. sending - recipients are being queued
+2. queued - every recipient is in the outbox
+3. cancelled
+4. failed - dispatch failed CAMPAIGN_MAX_ATTEMPTS times
+*/
+pub const CAMPAIGN_SCHEDULED: i16 = 0;
+pub const CAMPAIGN_SENDING: i16 = 1;
+pub const CAMPAIGN_QUEUED: i16 = 2;
+pub const CAMPAIGN_CANCELLED: i16 = 3;
+pub const CAMPAIGN_FAILED: i16 = 4;
+
+pub const CAMPAIGN_MAX_ATTEMPTS: i32 = 5;
+/// A campaign left in sending this long was claimed by an instance that
+/// died mid-dispatch; its transaction was rolled back, so it is retried.
+const CAMPAIGN_STALE_MINUTES: i64 = 15;
+
+/*
+segment
+
+users       - every user who is not blocked
+buyers      - users with an approved application, optionally of one stage
+subscribers - confirmed newsletter subscribers
+holders     - users whose wallet is among the holders
+*/
+pub const CAMPAIGN_SEGMENTS: &[&str] = &["users", "buyers", "subscribers", "holders"];
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct Campaign {
+    pub id:         i32,
+    pub user_id:    i32,
+    pub subject:    String,
+    pub html:       String,
+    pub segment:    String,
+    pub stage:      Option<i16>,
+    pub status:     i16,
+    pub scheduled:  chrono::NaiveDateTime,
+    pub created:    chrono::NaiveDateTime,
+    pub total:      i32,
+    pub attempts:   i32,
+    pub started:    Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CampaignRespData {
+    pub data: Vec<Campaign>,
+    pub next: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CampaignJson {
+    pub subject:   String,
+    pub html:      String,
+    pub segment:   String,
+    pub stage:     Option<i16>,
+    /// UTC; sent right away when missing
+    pub scheduled: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Queryable, Deserialize, Serialize)]
+pub struct CampaignRecipientResp {
+    pub email:      String,
+    pub name:       String,
+    pub status:     Option<i16>,
+    pub attempts:   Option<i32>,
+    pub last_error: Option<String>,
+    pub sent:       Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CampaignRecipientRespData {
+    pub data: Vec<CampaignRecipientResp>,
+    pub next: i64,
+}
+
+struct SegmentMember {
+    recipient:  Recipient,
+    subscriber: Option<Subscriber>,
+}
+
+impl Campaign {
+    pub fn get_status_name(status: i16) -> String {
+        return match status {
+            CAMPAIGN_SCHEDULED => "Scheduled".to_string(),
+            CAMPAIGN_SENDING =>   "Sending".to_string(),
+            CAMPAIGN_QUEUED =>    "Queued".to_string(),
+            CAMPAIGN_CANCELLED => "Cancelled".to_string(),
+            CAMPAIGN_FAILED =>    "Failed".to_string(),
+            _ =>                  "Uncnown status".to_string(),
+        }
+    }
+
+    pub fn create(user_id: i32, form: &CampaignJson) -> Result<Campaign, Error> {
+        if !CAMPAIGN_SEGMENTS.contains(&form.segment.as_str()) {
+            return Err(Error::BadRequest("Unknown segment: ".to_string() + &form.segment));
+        }
+        if form.subject.trim().is_empty() || form.html.trim().is_empty() {
+            return Err(Error::BadRequest("Subject and text are required".to_string()));
+        }
+        let _connection = establish_connection();
+        let created = chrono::Utc::now().naive_utc();
+        let new_form = NewCampaign {
+            user_id:   user_id,
+            subject:   form.subject.clone(),
+            html:      crate::mailer::sanitize_html(&form.html),
+            segment:   form.segment.clone(),
+            stage:     form.stage,
+            status:    CAMPAIGN_SCHEDULED,
+            scheduled: form.scheduled.unwrap_or(created),
+            created:   created,
+            total:     0,
+            attempts:  0,
+            started:   None,
+        };
+        Ok(diesel::insert_into(schema::campaigns::table)
+            .values(&new_form)
+            .get_result::<Campaign>(&_connection)?)
+    }
+
+    pub fn get_item(id: i32) -> Result<Campaign, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::campaigns::table
+            .filter(schema::campaigns::id.eq(id))
+            .first::<Campaign>(&_connection)?);
+    }
+
+    pub fn cancel(&self) -> Result<(), Error> {
+        if self.status != CAMPAIGN_SCHEDULED {
+            return Err(Error::BadRequest("Campaign is already ".to_string() + &Campaign::get_status_name(self.status)));
+        }
+        let _connection = establish_connection();
+        diesel::update(self)
+            .set(schema::campaigns::status.eq(CAMPAIGN_CANCELLED))
+            .execute(&_connection)?;
+        Ok(())
+    }
+
+    /// Claims the scheduled campaigns that are due, along with those
+    /// stuck in sending for `CAMPAIGN_STALE_MINUTES`. Every claim counts
+    /// as an attempt; stuck campaigns out of attempts are marked failed.
+    pub fn take_due() -> Vec<Campaign> {
+        let _connection = establish_connection();
+        let now = chrono::Utc::now().naive_utc();
+        let stale = now - chrono::Duration::minutes(CAMPAIGN_STALE_MINUTES);
+        diesel::update (
+            schema::campaigns::table
+                .filter(schema::campaigns::status.eq(CAMPAIGN_SENDING))
+                .filter(schema::campaigns::started.lt(stale))
+                .filter(schema::campaigns::attempts.ge(CAMPAIGN_MAX_ATTEMPTS))
+        )
+        .set(schema::campaigns::status.eq(CAMPAIGN_FAILED))
+        .execute(&_connection)
+        .expect("E.");
+        return diesel::update (
+            schema::campaigns::table
+                .filter(
+                    schema::campaigns::status.eq(CAMPAIGN_SCHEDULED)
+                        .and(schema::campaigns::scheduled.le(now))
+                    .or(schema::campaigns::status.eq(CAMPAIGN_SENDING)
+                        .and(schema::campaigns::started.lt(stale)))
+                )
+        )
+        .set((
+            schema::campaigns::status.eq(CAMPAIGN_SENDING),
+            schema::campaigns::started.eq(Some(now)),
+            schema::campaigns::attempts.eq(schema::campaigns::attempts + 1),
+        ))
+        .get_results::<Campaign>(&_connection)
+        .expect("E.");
+    }
+
+    fn get_members(&self, _connection: &PgConnection) -> Result<Vec<SegmentMember>, Error> {
+        let users = |emails: Vec<(String, String, String)>| -> Vec<SegmentMember> {
+            emails.into_iter()
+                .map(|(email, first_name, last_name)| SegmentMember {
+                    recipient:  Recipient::new(&email, &(first_name + " " + &last_name)),
+                    subscriber: None,
+                })
+                .collect()
+        };
+        let user_columns = (schema::users::email, schema::users::first_name, schema::users::last_name);
+        // USER and USERCANBUYTOCKEN, see models::user
+        let active_perms = vec![1i16, 2];
+
+        let members = match self.segment.as_str() {
+            "users" => users(schema::users::table
+                .filter(schema::users::perm.eq_any(active_perms))
+                .select(user_columns)
+                .load::<(String, String, String)>(_connection)?),
+            "buyers" => {
+                let mut query = schema::suggest_items::table
+                    .inner_join(schema::users::table.on(schema::users::email.eq(schema::suggest_items::email)))
+                    .filter(schema::suggest_items::status.eq(1))
+                    .filter(schema::users::perm.eq_any(active_perms))
+                    .select(user_columns)
+                    .into_boxed();
+                if let Some(stage) = self.stage {
+                    query = query.filter(schema::suggest_items::token_type.eq(stage));
+                }
+                users(query.load::<(String, String, String)>(_connection)?)
+            },
+            "holders" => users(schema::new_wallets::table
+                .inner_join(schema::users::table.on(schema::users::id.eq(schema::new_wallets::user_id)))
+                .inner_join(schema::holders::table.on(schema::holders::address.eq(schema::new_wallets::link)))
+                .filter(schema::new_wallets::verified.eq(true))
+                .filter(schema::users::perm.eq_any(active_perms))
+                .select(user_columns)
+                .load::<(String, String, String)>(_connection)?),
+            "subscribers" => schema::subscribers::table
+                .filter(schema::subscribers::status.eq(SUBSCRIBER_CONFIRMED))
+                .load::<Subscriber>(_connection)?
+                .into_iter()
+                .map(|s| SegmentMember {
+                    recipient:  Recipient::new(&s.email, "BJustCoin Community Member"),
+                    subscriber: Some(s),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut seen = HashSet::new();
+        Ok(members.into_iter()
+            .filter(|m| seen.insert(m.recipient.email.trim().to_lowercase()))
+            .collect())
+    }
+
+    /// Puts one message per segment member into the outbox. Messages are
+    /// spaced `CAMPAIGN_RATE` per minute apart so the outbox worker
+    /// delivers the campaign gradually alongside transactional mail.
+    pub fn dispatch(&self) -> Result<usize, Error> {
+        let _connection = establish_connection();
+        let rate = crate::vars::campaign_rate().max(1) as i64;
+        let text = mailer::html_to_text(&self.html);
+        let start = chrono::Utc::now().naive_utc();
+
+        _connection.transaction(|| {
+            // a claim that went stale may have been taken over meanwhile
+            let current = schema::campaigns::table
+                .filter(schema::campaigns::id.eq(self.id))
+                .for_update()
+                .first::<Campaign>(&_connection)?;
+            if current.status != CAMPAIGN_SENDING || current.attempts != self.attempts {
+                return Err(Error::BadRequest("Campaign was claimed again".to_string()));
+            }
+            let members = self.get_members(&_connection)?;
+            for (i, member) in members.iter().enumerate() {
+                let unsubscribe_url = member.subscriber.as_ref().map(|s| s.unsubscribe_url());
+                let mut mail = mailer::render("campaign", &serde_json::json!({
+                    "subject":         self.subject,
+                    "html":            self.html,
+                    "text":            text,
+                    "unsubscribe_url": unsubscribe_url,
+                }))?.into_outgoing(&member.recipient);
+                if let Some(subscriber) = member.subscriber.as_ref() {
+                    mail.headers = subscriber.unsubscribe_headers();
+                }
+                let next_attempt = start + chrono::Duration::milliseconds(i as i64 * 60_000 / rate);
+                let outbox = EmailOutbox::create_at(&_connection, "campaign", &mail, next_attempt)?;
+                diesel::insert_into(schema::campaign_recipients::table)
+                    .values(&NewCampaignRecipient {
+                        campaign_id: self.id,
+                        email:       member.recipient.email.clone(),
+                        name:        member.recipient.name.clone(),
+                        outbox_id:   outbox.id,
+                    })
+                    .execute(&_connection)?;
+            }
+            diesel::update(self)
+                .set((
+                    schema::campaigns::status.eq(CAMPAIGN_QUEUED),
+                    schema::campaigns::total.eq(members.len() as i32),
+                ))
+                .execute(&_connection)?;
+            Ok(members.len())
+        })
+    }
+
+    /// Puts a campaign whose dispatch failed back on schedule, or marks
+    /// it failed once it ran out of attempts.
+    pub fn reschedule(&self) {
+        let _connection = establish_connection();
+        let status = match self.attempts >= CAMPAIGN_MAX_ATTEMPTS {
+            true => CAMPAIGN_FAILED,
+            false => CAMPAIGN_SCHEDULED,
+        };
+        diesel::update (
+            schema::campaigns::table
+                .filter(schema::campaigns::id.eq(self.id))
+                .filter(schema::campaigns::status.eq(CAMPAIGN_SENDING))
+                .filter(schema::campaigns::attempts.eq(self.attempts))
+        )
+        .set(schema::campaigns::status.eq(status))
+        .execute(&_connection)
+        .expect("E.");
+    }
+
+    pub fn get(limit: i64, offset: i64) -> Vec<Campaign> {
+        let _connection = establish_connection();
+        return schema::campaigns::table
+            .order(schema::campaigns::id.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<Campaign>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(page: i64, limit: Option<i64>) -> CampaignRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<Campaign>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = Campaign::get(_limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = Campaign::get(_limit.into(), 0);
+        }
+        if Campaign::get(1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        CampaignRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+
+    pub fn get_recipients(&self, limit: i64, offset: i64) -> Vec<CampaignRecipientResp> {
+        let _connection = establish_connection();
+        return schema::campaign_recipients::table
+            .left_join(schema::email_outbox::table.on(schema::email_outbox::id.eq(schema::campaign_recipients::outbox_id)))
+            .filter(schema::campaign_recipients::campaign_id.eq(self.id))
+            .order(schema::campaign_recipients::id.asc())
+            .limit(limit)
+            .offset(offset)
+            .select((
+                schema::campaign_recipients::email,
+                schema::campaign_recipients::name,
+                schema::email_outbox::status.nullable(),
+                schema::email_outbox::attempts.nullable(),
+                schema::email_outbox::last_error.nullable(),
+                schema::email_outbox::sent.nullable(),
+            ))
+            .load::<CampaignRecipientResp>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_recipients_list(&self, page: i64, limit: Option<i64>) -> CampaignRecipientRespData {
+        let _limit = get_limit(limit, 50);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<CampaignRecipientResp>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = self.get_recipients(_limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = self.get_recipients(_limit.into(), 0);
+        }
+        if self.get_recipients(1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        CampaignRecipientRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="campaigns"]
+pub struct NewCampaign {
+    pub user_id:    i32,
+    pub subject:    String,
+    pub html:       String,
+    pub segment:    String,
+    pub stage:      Option<i16>,
+    pub status:     i16,
+    pub scheduled:  chrono::NaiveDateTime,
+    pub created:    chrono::NaiveDateTime,
+    pub total:      i32,
+    pub attempts:   i32,
+    pub started:    Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="campaign_recipients"]
+pub struct NewCampaignRecipient {
+    pub campaign_id: i32,
+    pub email:       String,
+    pub name:        String,
+    pub outbox_id:   i32,
+}
@@ -0,0 +1,391 @@
+use crate::schema;
+use crate::schema::{
+    chain_events,
+    chain_cursors,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    BoolExpressionMethods,
+    RunQueryDsl,
+    Connection,
+    PgConnection,
+};
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Text};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use crate::utils::{establish_connection, parse_units, WalletAddress};
+use crate::errors::Error;
+use crate::models::{NewHolder, Notification, Payment, PAYMENT_CONFIRMED, PAYMENT_AWAITING, PAYMENT_PENDING};
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/*
+kind
+
+transfer - Transfer(address,address,uint256) of the token contract
+purchase - the purchase event of the sale contract
+payment  - Transfer of the payment token to the payment address
+*/
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct ChainEvent {
+    pub id:           i32,
+    pub contract:     String,
+    pub kind:         String,
+    pub block_number: i64,
+    pub block_hash:   String,
+    pub tx_hash:      String,
+    pub log_index:    i32,
+    pub from_address: String,
+    pub to_address:   String,
+    pub amount:       String,
+    pub created:      chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize, Insertable)]
+#[table_name="chain_events"]
+pub struct NewChainEvent {
+    pub contract:     String,
+    pub kind:         String,
+    pub block_number: i64,
+    pub block_hash:   String,
+    pub tx_hash:      String,
+    pub log_index:    i32,
+    pub from_address: String,
+    pub to_address:   String,
+    pub amount:       String,
+    pub created:      chrono::NaiveDateTime,
+}
+
+/// Last block of a contract that was scanned, with its hash so a reorg
+/// deeper than the confirmation depth can still be noticed.
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable, Insertable)]
+#[table_name="chain_cursors"]
+#[primary_key(contract)]
+pub struct ChainCursor {
+    pub contract:     String,
+    pub block_number: i64,
+    pub block_hash:   Option<String>,
+}
+
+#[derive(Debug, QueryableByName)]
+struct HolderBalance {
+    #[sql_type = "Text"]
+    balance: String,
+}
+
+impl ChainCursor {
+    pub fn get_or_create(contract: &str, start_block: i64) -> ChainCursor {
+        let _connection = establish_connection();
+        let item_some = schema::chain_cursors::table
+            .filter(schema::chain_cursors::contract.eq(contract))
+            .first::<ChainCursor>(&_connection);
+        if let Ok(item) = item_some {
+            return item;
+        }
+        let form = ChainCursor {
+            contract:     contract.to_string(),
+            block_number: start_block,
+            block_hash:   None,
+        };
+        diesel::insert_into(schema::chain_cursors::table)
+            .values(&form)
+            .get_result::<ChainCursor>(&_connection)
+            .expect("Error saving chain cursor.")
+    }
+
+    /// Drops everything after `block_number` and moves the cursor back to it.
+    pub fn rewind(&self, block_number: i64) -> Result<(), Error> {
+        let _connection = establish_connection();
+        _connection.transaction(|| {
+            let removed = diesel::delete (
+                schema::chain_events::table
+                    .filter(schema::chain_events::contract.eq(&self.contract))
+                    .filter(schema::chain_events::block_number.gt(block_number))
+            )
+            .get_results::<ChainEvent>(&_connection)?;
+            diesel::update(self)
+                .set((
+                    schema::chain_cursors::block_number.eq(block_number),
+                    schema::chain_cursors::block_hash.eq(None::<String>),
+                ))
+                .execute(&_connection)?;
+            ChainEvent::refresh_holders(&_connection, &removed)?;
+            ChainEvent::revert_payments(&_connection, &removed)?;
+            Ok(())
+        })
+    }
+}
+
+impl ChainEvent {
+    /// Stores a scanned block range and advances the cursor in one
+    /// transaction, then brings holder balances and payments up to date.
+    pub fn store(cursor: &ChainCursor, events: Vec<NewChainEvent>, block_number: i64, block_hash: String) -> Result<usize, Error> {
+        let _connection = establish_connection();
+        let (count, notifications) = _connection.transaction(|| {
+            let stored = diesel::insert_into(schema::chain_events::table)
+                .values(&events)
+                .on_conflict((schema::chain_events::tx_hash, schema::chain_events::log_index))
+                .do_nothing()
+                .get_results::<ChainEvent>(&_connection)?;
+            diesel::update(cursor)
+                .set((
+                    schema::chain_cursors::block_number.eq(block_number),
+                    schema::chain_cursors::block_hash.eq(Some(block_hash)),
+                ))
+                .execute(&_connection)?;
+            ChainEvent::refresh_holders(&_connection, &stored)?;
+            let tx_hashes = stored.iter()
+                .filter(|i| i.kind == "payment")
+                .map(|i| i.tx_hash.clone())
+                .collect();
+            let notifications = ChainEvent::confirm_payments(&_connection, tx_hashes)?;
+            Ok((stored.len(), notifications))
+        })?;
+        for i in notifications.iter() {
+            i.push();
+        }
+        Ok(count)
+    }
+
+    /// Recomputes on-chain balances of every address touched by `events`
+    /// from the stored transfers.
+    fn refresh_holders(_connection: &PgConnection, events: &Vec<ChainEvent>) -> Result<(), Error> {
+        let stage = crate::vars::chain_holder_stage();
+        let mut addresses = HashSet::new();
+        for i in events.iter().filter(|i| i.kind == "transfer") {
+            addresses.insert(i.from_address.clone());
+            addresses.insert(i.to_address.clone());
+        }
+        addresses.remove(ZERO_ADDRESS);
+
+        for address in addresses.iter() {
+            let balance = sql_query(
+                "SELECT ((COALESCE(SUM(CASE WHEN to_address = $1 THEN CAST(amount AS NUMERIC) ELSE 0 END), 0)
+                        - COALESCE(SUM(CASE WHEN from_address = $1 THEN CAST(amount AS NUMERIC) ELSE 0 END), 0))
+                        / POWER(10::NUMERIC, $2))::TEXT AS balance
+                 FROM chain_events
+                 WHERE kind = 'transfer' AND (to_address = $1 OR from_address = $1)"
+            )
+                .bind::<Text, _>(address)
+                .bind::<Integer, _>(crate::vars::token_decimals() as i32)
+                .get_result::<HolderBalance>(_connection)?;
+            let count2 = trim_decimal(&balance.balance);
+
+            if count2 == "0" {
+                diesel::delete (
+                    schema::holders::table
+                        .filter(schema::holders::address.eq(address))
+                        .filter(schema::holders::stage.eq(&stage))
+                )
+                .execute(_connection)?;
+                continue;
+            }
+            let form = NewHolder {
+                address: address.clone(),
+                count:   0,
+                stage:   stage.clone(),
+                count2:  count2,
+            };
+            diesel::insert_into(schema::holders::table)
+                .values(&form)
+                .on_conflict((schema::holders::address, schema::holders::stage))
+                .do_update()
+                .set(schema::holders::count2.eq(&form.count2))
+                .execute(_connection)?;
+        }
+        Ok(())
+    }
+
+    /// Payments recorded with one of `tx_hashes` that the stored transfers
+    /// of their transaction paid, see `pays`. Called both when transfers
+    /// are stored and when a transaction is recorded, so the order of the
+    /// two does not matter. Returns the notifications for their payers,
+    /// to push after commit.
+    pub fn confirm_payments(_connection: &PgConnection, tx_hashes: Vec<String>) -> Result<Vec<Notification>, Error> {
+        if tx_hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let contract = match crate::vars::payment_token_contract() {
+            Some(contract) => contract.to_lowercase(),
+            None => return Ok(Vec::new()),
+        };
+        let events = schema::chain_events::table
+            .filter(schema::chain_events::kind.eq("payment"))
+            .filter(schema::chain_events::tx_hash.eq_any(&tx_hashes))
+            .load::<ChainEvent>(_connection)?;
+        if events.is_empty() {
+            return Ok(Vec::new());
+        }
+        let transfers: Vec<&ChainEvent> = events.iter().collect();
+        let payments = schema::payments::table
+            .filter(schema::payments::tx_hash.eq_any(&tx_hashes))
+            .filter(
+                schema::payments::status.eq(PAYMENT_AWAITING)
+                    .or(schema::payments::status.eq(PAYMENT_PENDING))
+            )
+            .for_update()
+            .load::<Payment>(_connection)?;
+        let currency = crate::vars::payment_currency();
+        let decimals = crate::vars::payment_token_decimals();
+        let mut notifications = Vec::new();
+        for i in payments.iter().filter(|i| pays(&transfers, i, &currency, &contract, decimals)) {
+            diesel::update(i)
+                .set((
+                    schema::payments::status.eq(PAYMENT_CONFIRMED),
+                    schema::payments::confirmed.eq(Some(chrono::Utc::now().naive_utc())),
+                ))
+                .execute(_connection)?;
+            notifications.push(i.notify_confirmed(_connection)?);
+        }
+        Ok(notifications)
+    }
+
+    /// Payments confirmed by transfers a reorg dropped go back to pending
+    /// until the transfer is seen again.
+    fn revert_payments(_connection: &PgConnection, events: &Vec<ChainEvent>) -> Result<usize, Error> {
+        let tx_hashes: Vec<String> = events.iter()
+            .filter(|i| i.kind == "payment")
+            .map(|i| i.tx_hash.clone())
+            .collect();
+        if tx_hashes.is_empty() {
+            return Ok(0);
+        }
+        Ok(diesel::update (
+            schema::payments::table
+                .filter(schema::payments::tx_hash.eq_any(tx_hashes))
+                .filter(schema::payments::status.eq(PAYMENT_CONFIRMED))
+        )
+        .set((
+            schema::payments::status.eq(PAYMENT_PENDING),
+            schema::payments::confirmed.eq(None::<chrono::NaiveDateTime>),
+        ))
+        .execute(_connection)?)
+    }
+}
+
+/// Whether the `payment` transfers of the payment's transaction send at
+/// least the amount due to its address, on the token contract of its
+/// currency. `amount` of a transfer is in base units of the token.
+fn pays(transfers: &[&ChainEvent], payment: &Payment, currency: &str, contract: &str, decimals: u32) -> bool {
+    if payment.currency != currency {
+        return false;
+    }
+    let due = match parse_units(&payment.amount, decimals) {
+        Some(due) => due,
+        None => return false,
+    };
+    let to = match WalletAddress::parse(&payment.address) {
+        Ok(to) => to.to_string(),
+        Err(_) => return false,
+    };
+    let paid = transfers.iter()
+        .filter(|i| Some(&i.tx_hash) == payment.tx_hash.as_ref())
+        .filter(|i| i.kind == "payment" && i.contract == contract && i.to_address == to)
+        .filter_map(|i| i.amount.parse::<u128>().ok())
+        .fold(0u128, |sum, amount| sum.saturating_add(amount));
+    due > 0 && paid >= due
+}
+
+fn trim_decimal(value: &str) -> String {
+    if !value.contains('.') {
+        return value.to_string();
+    }
+    let value = value.trim_end_matches('0').trim_end_matches('.');
+    match value.is_empty() || value == "-" {
+        true => "0".to_string(),
+        false => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+    const TO: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const TX: &str = "0x01";
+
+    fn transfer(contract: &str, to: &str, amount: &str) -> ChainEvent {
+        ChainEvent {
+            id:           0,
+            contract:     contract.to_string(),
+            kind:         "payment".to_string(),
+            block_number: 1,
+            block_hash:   "0x02".to_string(),
+            tx_hash:      TX.to_string(),
+            log_index:    0,
+            from_address: ZERO_ADDRESS.to_string(),
+            to_address:   to.to_string(),
+            amount:       amount.to_string(),
+            created:      chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    fn payment(amount: &str) -> Payment {
+        let now = chrono::Utc::now().naive_utc();
+        Payment {
+            id:              1,
+            suggest_item_id: 1,
+            user_id:         1,
+            amount:          amount.to_string(),
+            currency:        "USDT".to_string(),
+            address:         TO.to_lowercase(),
+            tx_hash:         Some(TX.to_string()),
+            status:          PAYMENT_PENDING,
+            created:         now,
+            expires:         now,
+            confirmed:       None,
+        }
+    }
+
+    fn check(events: &[ChainEvent], payment: &Payment) -> bool {
+        let transfers: Vec<&ChainEvent> = events.iter().collect();
+        pays(&transfers, payment, "USDT", USDT, 6)
+    }
+
+    #[test]
+    fn exact_amount_pays() {
+        assert!(check(&[transfer(USDT, TO, "150250000")], &payment("150.25")));
+    }
+
+    #[test]
+    fn split_transfers_add_up() {
+        let events = [transfer(USDT, TO, "100000000"), transfer(USDT, TO, "50250000")];
+        assert!(check(&events, &payment("150.25")));
+    }
+
+    #[test]
+    fn short_amount_does_not_pay() {
+        assert!(!check(&[transfer(USDT, TO, "150249999")], &payment("150.25")));
+    }
+
+    #[test]
+    fn other_recipient_does_not_pay() {
+        let other = "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359";
+        assert!(!check(&[transfer(USDT, other, "150250000")], &payment("150.25")));
+    }
+
+    #[test]
+    fn other_token_does_not_pay() {
+        let other = "0x00000000000000000000000000000000000000aa";
+        assert!(!check(&[transfer(other, TO, "150250000")], &payment("150.25")));
+    }
+
+    #[test]
+    fn other_currency_does_not_pay() {
+        let mut payment = payment("150.25");
+        payment.currency = "USDC".to_string();
+        assert!(!check(&[transfer(USDT, TO, "150250000")], &payment));
+    }
+
+    #[test]
+    fn other_transaction_does_not_pay() {
+        let mut payment = payment("150.25");
+        payment.tx_hash = Some("0x03".to_string());
+        assert!(!check(&[transfer(USDT, TO, "150250000")], &payment));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::schema;
+use crate::schema::{
+    email_events,
+    email_suppressions,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+    PgConnection,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, get_limit};
+use crate::errors::Error;
+
+/// One entry of a SendGrid event webhook batch. `outbox_id` is the unique
+/// argument the SendGrid transport attaches to every message.
+#[derive(Debug, Deserialize)]
+pub struct SendGridEvent {
+    pub email:         String,
+    pub event:         String,
+    pub timestamp:     i64,
+    pub sg_event_id:   Option<String>,
+    pub sg_message_id: Option<String>,
+    pub reason:        Option<String>,
+    #[serde(rename = "type")]
+    pub bounce_type:   Option<String>,
+    pub outbox_id:     Option<serde_json::Value>,
+}
+
+impl SendGridEvent {
+    fn get_outbox_id(&self) -> Option<i32> {
+        match self.outbox_id.as_ref()? {
+            serde_json::Value::String(s) => s.parse::<i32>().ok(),
+            serde_json::Value::Number(n) => n.as_i64().map(|n| n as i32),
+            _ => None,
+        }
+    }
+    /// Hard bounces, spam complaints and unsubscribes stop all further
+    /// mail; "blocked" bounces are temporary and do not.
+    fn suppresses(&self) -> bool {
+        match self.event.as_str() {
+            "bounce" => self.bounce_type.as_deref() != Some("blocked"),
+            "spamreport" | "unsubscribe" | "group_unsubscribe" => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct EmailEvent {
+    pub id:            i32,
+    pub outbox_id:     Option<i32>,
+    pub email:         String,
+    pub event:         String,
+    pub reason:        Option<String>,
+    pub sg_event_id:   Option<String>,
+    pub sg_message_id: Option<String>,
+    pub created:       chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EmailEventRespData {
+    pub data: Vec<EmailEvent>,
+    pub next: i64,
+}
+
+impl EmailEvent {
+    /// Stores a webhook batch. Events SendGrid delivers twice are skipped by
+    /// `sg_event_id`.
+    pub fn create(events: Vec<SendGridEvent>) -> Result<usize, Error> {
+        let _connection = establish_connection();
+        _connection.transaction(|| {
+            let mut count = 0;
+            for i in events.iter() {
+                let form = NewEmailEvent {
+                    outbox_id:     i.get_outbox_id(),
+                    email:         i.email.trim().to_lowercase(),
+                    event:         i.event.clone(),
+                    reason:        i.reason.clone(),
+                    sg_event_id:   i.sg_event_id.clone(),
+                    sg_message_id: i.sg_message_id.clone(),
+                    created:       chrono::NaiveDateTime::from_timestamp_opt(i.timestamp, 0)
+                        .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+                };
+                let inserted = diesel::insert_into(schema::email_events::table)
+                    .values(&form)
+                    .on_conflict_do_nothing()
+                    .execute(&_connection)?;
+                if inserted > 0 && i.suppresses() {
+                    EmailSuppression::create_with(&_connection, &form.email, &i.event)?;
+                }
+                count += inserted;
+            }
+            Ok(count)
+        })
+    }
+
+    pub fn get(email: Option<String>, limit: i64, offset: i64) -> Vec<EmailEvent> {
+        let _connection = establish_connection();
+        let mut query = schema::email_events::table
+            .order(schema::email_events::id.desc())
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(email) = email {
+            query = query.filter(schema::email_events::email.eq(email.trim().to_lowercase()));
+        }
+        return query
+            .load::<EmailEvent>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(email: Option<String>, page: i64, limit: Option<i64>) -> EmailEventRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<EmailEvent>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = EmailEvent::get(email.clone(), _limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = EmailEvent::get(email.clone(), _limit.into(), 0);
+        }
+        if EmailEvent::get(email, 1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        EmailEventRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="email_events"]
+pub struct NewEmailEvent {
+    pub outbox_id:     Option<i32>,
+    pub email:         String,
+    pub event:         String,
+    pub reason:        Option<String>,
+    pub sg_event_id:   Option<String>,
+    pub sg_message_id: Option<String>,
+    pub created:       chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct EmailSuppression {
+    pub id:      i32,
+    pub email:   String,
+    pub reason:  String,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EmailSuppressionRespData {
+    pub data: Vec<EmailSuppression>,
+    pub next: i64,
+}
+
+impl EmailSuppression {
+    pub fn create_with(_connection: &PgConnection, email: &str, reason: &str) -> Result<(), Error> {
+        diesel::insert_into(schema::email_suppressions::table)
+            .values(&NewEmailSuppression {
+                email:   email.trim().to_lowercase(),
+                reason:  reason.to_string(),
+                created: chrono::Utc::now().naive_utc(),
+            })
+            .on_conflict_do_nothing()
+            .execute(_connection)?;
+        Ok(())
+    }
+
+    pub fn is_suppressed_with(_connection: &PgConnection, email: &str) -> bool {
+        schema::email_suppressions::table
+            .filter(schema::email_suppressions::email.eq(email.trim().to_lowercase()))
+            .select(schema::email_suppressions::id)
+            .first::<i32>(_connection)
+            .is_ok()
+    }
+
+    pub fn delete(id: i32) -> Result<(), Error> {
+        let _connection = establish_connection();
+        diesel::delete(schema::email_suppressions::table.filter(schema::email_suppressions::id.eq(id)))
+            .execute(&_connection)?;
+        Ok(())
+    }
+
+    pub fn get(limit: i64, offset: i64) -> Vec<EmailSuppression> {
+        let _connection = establish_connection();
+        return schema::email_suppressions::table
+            .order(schema::email_suppressions::id.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<EmailSuppression>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(page: i64, limit: Option<i64>) -> EmailSuppressionRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<EmailSuppression>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = EmailSuppression::get(_limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = EmailSuppression::get(_limit.into(), 0);
+        }
+        if EmailSuppression::get(1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        EmailSuppressionRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="email_suppressions"]
+pub struct NewEmailSuppression {
+    pub email:   String,
+    pub reason:  String,
+    pub created: chrono::NaiveDateTime,
+}
@@ -1,9 +1,31 @@
 mod user;
 mod email;
 mod other;
+mod payment;
+mod wallet;
+mod white_list;
+mod chain;
+mod outbox;
+mod notify;
+mod subscriber;
+mod campaign;
+mod email_event;
+mod notification;
+mod ws_ticket;
 
 pub use self::{
     user::*,
     email::*,
     other::*,
+    payment::*,
+    wallet::*,
+    white_list::*,
+    chain::*,
+    outbox::*,
+    notify::*,
+    subscriber::*,
+    campaign::*,
+    email_event::*,
+    notification::*,
+    ws_ticket::*,
 };
@@ -0,0 +1,144 @@
+use crate::schema;
+use crate::schema::notifications;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    PgConnection,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, get_limit};
+use crate::errors::Error;
+use crate::websocket::{Audience, EventType, MessageToClient};
+
+/// A message to one user about their own applications and payments,
+/// stored so it can be read later and pushed over the websocket.
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct Notification {
+    pub id:        i32,
+    pub user_id:   i32,
+    pub types:     String,
+    pub target_id: Option<i32>,
+    pub text:      String,
+    pub is_read:   bool,
+    pub created:   chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct NotificationRespData {
+    pub data: Vec<Notification>,
+    pub next: i64,
+}
+
+impl Notification {
+    /// Stores a notification with `text` translated into the user's
+    /// locale. Call `push` once the surrounding transaction is committed.
+    pub fn create_with(_connection: &PgConnection, user_id: i32, types: EventType, target_id: Option<i32>, text: &str) -> Result<Notification, Error> {
+        let locale = schema::users::table
+            .filter(schema::users::id.eq(user_id))
+            .select(schema::users::locale)
+            .first::<String>(_connection)?;
+        let form = NewNotification {
+            user_id:   user_id,
+            types:     types.name().to_string(),
+            target_id: target_id,
+            text:      crate::i18n::t(&locale, text),
+            is_read:   false,
+            created:   chrono::Utc::now().naive_utc(),
+        };
+        Ok(diesel::insert_into(schema::notifications::table)
+            .values(&form)
+            .get_result::<Notification>(_connection)?)
+    }
+
+    /// Sends the notification to the user's open websocket sessions.
+    pub fn push(&self) {
+        let types = match serde_json::from_value::<EventType>(serde_json::Value::from(self.types.clone())) {
+            Ok(types) => types,
+            Err(_) => return,
+        };
+        crate::websocket::publish (
+            Audience::User(self.user_id),
+            MessageToClient::new(types, self.target_id.unwrap_or(0), serde_json::json!(self)),
+        );
+    }
+
+    pub fn count_unread(user_id: i32) -> i64 {
+        let _connection = establish_connection();
+        return schema::notifications::table
+            .filter(schema::notifications::user_id.eq(user_id))
+            .filter(schema::notifications::is_read.eq(false))
+            .count()
+            .get_result::<i64>(&_connection)
+            .expect("E.");
+    }
+
+    /// Marks `ids` read, or every notification of the user if None.
+    pub fn mark_read(user_id: i32, ids: Option<Vec<i32>>) -> Result<usize, Error> {
+        let _connection = establish_connection();
+        let unread = schema::notifications::table
+            .filter(schema::notifications::user_id.eq(user_id))
+            .filter(schema::notifications::is_read.eq(false));
+        let count = match ids {
+            Some(ids) => diesel::update(unread.filter(schema::notifications::id.eq_any(ids)))
+                .set(schema::notifications::is_read.eq(true))
+                .execute(&_connection)?,
+            None => diesel::update(unread)
+                .set(schema::notifications::is_read.eq(true))
+                .execute(&_connection)?,
+        };
+        Ok(count)
+    }
+
+    pub fn get(user_id: i32, unread: bool, limit: i64, offset: i64) -> Vec<Notification> {
+        let _connection = establish_connection();
+        let mut query = schema::notifications::table
+            .filter(schema::notifications::user_id.eq(user_id))
+            .order(schema::notifications::id.desc())
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if unread {
+            query = query.filter(schema::notifications::is_read.eq(false));
+        }
+        return query
+            .load::<Notification>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(user_id: i32, unread: bool, page: i64, limit: Option<i64>) -> NotificationRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<Notification>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = Notification::get(user_id, unread, _limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = Notification::get(user_id, unread, _limit.into(), 0);
+        }
+        if Notification::get(user_id, unread, 1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        NotificationRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="notifications"]
+pub struct NewNotification {
+    pub user_id:   i32,
+    pub types:     String,
+    pub target_id: Option<i32>,
+    pub text:      String,
+    pub is_read:   bool,
+    pub created:   chrono::NaiveDateTime,
+}
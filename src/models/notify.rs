@@ -0,0 +1,181 @@
+use crate::schema;
+use crate::schema::notification_subscriptions;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    JoinOnDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::establish_connection;
+use crate::errors::Error;
+use crate::mailer::Recipient;
+use crate::models::User;
+
+/*
+event
+
+new_user        - someone signed up
+new_application - a token purchase application was submitted
+new_subscriber  - someone subscribed to the newsletter
+approval        - an application was approved
+*/
+pub const NOTIFY_EVENTS: &[&str] = &["new_user", "new_application", "new_subscriber", "approval"];
+pub const NOTIFY_CHANNELS: &[&str] = &["email"];
+
+/// Either an admin's subscription (`user_id`) or one of an outside
+/// address (`email` and `name`), such as an external monitor.
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct NotificationSubscription {
+    pub id:      i32,
+    pub user_id: Option<i32>,
+    pub email:   Option<String>,
+    pub name:    Option<String>,
+    pub event:   String,
+    pub channel: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotificationSubscriptionJson {
+    pub event:   String,
+    pub channel: String,
+}
+
+impl NotificationSubscription {
+    pub fn get_for_user(user_id: i32) -> Vec<NotificationSubscription> {
+        let _connection = establish_connection();
+        return schema::notification_subscriptions::table
+            .filter(schema::notification_subscriptions::user_id.eq(user_id))
+            .order(schema::notification_subscriptions::id.asc())
+            .load::<NotificationSubscription>(&_connection)
+            .expect("E.");
+    }
+
+    fn check_items(items: &Vec<NotificationSubscriptionJson>) -> Result<(), Error> {
+        for i in items.iter() {
+            if !NOTIFY_EVENTS.contains(&i.event.as_str()) {
+                return Err(Error::BadRequest("Unknown event: ".to_string() + &i.event));
+            }
+            if !NOTIFY_CHANNELS.contains(&i.channel.as_str()) {
+                return Err(Error::BadRequest("Unknown channel: ".to_string() + &i.channel));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every subscription of `user_id` with `items`.
+    pub fn set_for_user(user_id: i32, items: &Vec<NotificationSubscriptionJson>) -> Result<Vec<NotificationSubscription>, Error> {
+        NotificationSubscription::check_items(items)?;
+        let _connection = establish_connection();
+        _connection.transaction(|| {
+            diesel::delete (
+                schema::notification_subscriptions::table
+                    .filter(schema::notification_subscriptions::user_id.eq(user_id))
+            )
+            .execute(&_connection)?;
+            let forms: Vec<NewNotificationSubscription> = items.iter()
+                .map(|i| NewNotificationSubscription {
+                    user_id: Some(user_id),
+                    email:   None,
+                    name:    None,
+                    event:   i.event.clone(),
+                    channel: i.channel.clone(),
+                })
+                .collect();
+            let _items = diesel::insert_into(schema::notification_subscriptions::table)
+                .values(&forms)
+                .on_conflict_do_nothing()
+                .get_results::<NotificationSubscription>(&_connection)?;
+            Ok(_items)
+        })
+    }
+
+    /// Subscriptions of outside addresses.
+    pub fn get_external() -> Vec<NotificationSubscription> {
+        let _connection = establish_connection();
+        return schema::notification_subscriptions::table
+            .filter(schema::notification_subscriptions::user_id.is_null())
+            .order((schema::notification_subscriptions::email.asc(), schema::notification_subscriptions::id.asc()))
+            .load::<NotificationSubscription>(&_connection)
+            .expect("E.");
+    }
+
+    /// Replaces every subscription of the outside address `email` with
+    /// `items`; an empty list removes the address.
+    pub fn set_for_email(email: &str, name: &str, items: &Vec<NotificationSubscriptionJson>) -> Result<Vec<NotificationSubscription>, Error> {
+        NotificationSubscription::check_items(items)?;
+        let email = email.trim();
+        if !email.contains('@') || email.len() > 100 {
+            return Err(Error::BadRequest("Invalid email".to_string()));
+        }
+        let _connection = establish_connection();
+        _connection.transaction(|| {
+            diesel::delete (
+                schema::notification_subscriptions::table
+                    .filter(schema::notification_subscriptions::user_id.is_null())
+                    .filter(schema::notification_subscriptions::email.eq(email))
+            )
+            .execute(&_connection)?;
+            let forms: Vec<NewNotificationSubscription> = items.iter()
+                .map(|i| NewNotificationSubscription {
+                    user_id: None,
+                    email:   Some(email.to_string()),
+                    name:    Some(name.trim().to_string()),
+                    event:   i.event.clone(),
+                    channel: i.channel.clone(),
+                })
+                .collect();
+            let _items = diesel::insert_into(schema::notification_subscriptions::table)
+                .values(&forms)
+                .on_conflict_do_nothing()
+                .get_results::<NotificationSubscription>(&_connection)?;
+            Ok(_items)
+        })
+    }
+
+    /// Everyone subscribed to `event` on `channel`, with the first name
+    /// templates greet them by. Blocked admins drop out because they no
+    /// longer pass `is_admin`.
+    pub fn get_recipients(event: &str, channel: &str) -> Vec<(Recipient, String)> {
+        let _connection = establish_connection();
+        let admins = schema::notification_subscriptions::table
+            .inner_join(schema::users::table.on(schema::users::id.nullable().eq(schema::notification_subscriptions::user_id)))
+            .filter(schema::notification_subscriptions::event.eq(event))
+            .filter(schema::notification_subscriptions::channel.eq(channel))
+            .select(schema::users::all_columns)
+            .load::<User>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .filter(|u| u.is_admin())
+            .map(|u| {
+                let name = u.first_name.clone() + &" ".to_string() + &u.last_name;
+                (Recipient::new(&u.email, &name), u.first_name)
+            });
+        let external = schema::notification_subscriptions::table
+            .filter(schema::notification_subscriptions::user_id.is_null())
+            .filter(schema::notification_subscriptions::event.eq(event))
+            .filter(schema::notification_subscriptions::channel.eq(channel))
+            .load::<NotificationSubscription>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .filter_map(|i| {
+                let name = i.name.unwrap_or_default();
+                let first_name = name.split_whitespace().next().unwrap_or_default().to_string();
+                i.email.map(|email| (Recipient::new(&email, &name), first_name))
+            });
+        return admins.chain(external).collect();
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="notification_subscriptions"]
+pub struct NewNotificationSubscription {
+    pub user_id: Option<i32>,
+    pub email:   Option<String>,
+    pub name:    Option<String>,
+    pub event:   String,
+    pub channel: String,
+}
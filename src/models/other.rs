@@ -10,12 +10,19 @@ use crate::diesel::{
     Insertable,
     QueryDsl,
     ExpressionMethods,
+    PgTextExpressionMethods,
     RunQueryDsl,
+    Connection,
 };
+use diesel::dsl::sql;
+use diesel::sql_query;
+use diesel::sql_types::{Bool, BigInt, Double, Text};
 use serde::{Serialize, Deserialize};
-use crate::utils::{establish_connection, get_limit};
+use crate::utils::{establish_connection, get_limit, WalletAddress};
+use crate::errors::Error;
 use actix_web::web::Json;
-use crate::models::{SmallUser, User};
+use crate::models::{SmallUser, User, NewWallet, NewWhiteList, Notification};
+use crate::websocket::EventType;
 
 
 #[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
@@ -46,7 +53,7 @@ pub struct NewSuggestJson {
     pub phone:    String,
     pub mobile:   String,
     pub is_agree: String,
-    pub address:  String,
+    pub address:  WalletAddress,
     pub tokens:   String,
 }
 
@@ -78,6 +85,23 @@ pub struct ApplicationUser {
 }
 
 impl SuggestItem {
+    /// Whether application `id` was submitted by user `user_id`.
+    pub fn is_owner(id: i32, user_id: i32) -> bool {
+        let _connection = establish_connection();
+        let email = match schema::users::table
+            .filter(schema::users::id.eq(user_id))
+            .select(schema::users::email)
+            .first::<String>(&_connection) {
+                Ok(email) => email,
+                Err(_) => return false,
+            };
+        return schema::suggest_items::table
+            .filter(schema::suggest_items::id.eq(id))
+            .filter(schema::suggest_items::email.eq(email))
+            .select(schema::suggest_items::id)
+            .first::<i32>(&_connection)
+            .is_ok();
+    }
     pub fn get_user_data(id: i32) -> ApplicationUser {
         let _connection = establish_connection();
         return schema::suggest_items::table
@@ -90,22 +114,86 @@ impl SuggestItem {
             .first::<ApplicationUser>(&_connection)
             .expect("E");
     }
-    pub fn agree_application(id: i32, tokens: String, ico_stage: i16) -> () {
+    /// How many applications of `stage` are approved.
+    pub fn count_approved(stage: i16) -> i64 {
         let _connection = establish_connection();
-        let item_some = schema::suggest_items::table
-                .filter(schema::suggest_items::id.eq(id))
-                .first::<SuggestItem>(&_connection);
-            if item_some.is_ok() {
-                let item = item_some.expect("E.");
-                diesel::update(&item)
-                    .set((
-                        schema::suggest_items::status.eq(1),
-                        schema::suggest_items::tokens.eq(tokens),
-                        schema::suggest_items::token_type.eq(ico_stage),
-                    ))
-                    .execute(&_connection)
-                    .expect("E");
-            }
+        return schema::suggest_items::table
+            .filter(schema::suggest_items::status.eq(1))
+            .filter(schema::suggest_items::token_type.eq(stage))
+            .count()
+            .get_result::<i64>(&_connection)
+            .expect("E.");
+    }
+    /// Approves the application and, in the same transaction, links its
+    /// wallet to the applicant, puts them on the stage white list and opens
+    /// the payment.
+    pub fn agree_application(id: i32, tokens: String, ico_stage: i16, amount: String) -> Result<(), Error> {
+        let _connection = establish_connection();
+        let item = schema::suggest_items::table
+            .filter(schema::suggest_items::id.eq(id))
+            .first::<SuggestItem>(&_connection)?;
+        let address = WalletAddress::parse(&item.address).map_err(Error::BadRequest)?;
+        let _user = schema::users::table
+            .filter(schema::users::email.eq(&item.email))
+            .first::<User>(&_connection)?;
+
+        let notification = _connection.transaction(|| {
+            let item = diesel::update(&item)
+                .set((
+                    schema::suggest_items::status.eq(1),
+                    schema::suggest_items::tokens.eq(&tokens),
+                    schema::suggest_items::token_type.eq(ico_stage),
+                ))
+                .get_result::<SuggestItem>(&_connection)?;
+            NewWallet::create_with(&_connection, _user.id, &address)?;
+            NewWhiteList::create_with(&_connection, _user.id, ico_stage, tokens.clone())?;
+            crate::models::Payment::create_with(&_connection, &item, _user.id, amount.clone())?;
+            Notification::create_with(&_connection, _user.id, EventType::Approval, Some(item.id), "Your application is approved")
+        })?;
+        notification.push();
+        Ok(())
+    }
+
+    /// Rejects a new application and tells the applicant.
+    pub fn reject_application(id: i32, request_user: &User) -> Result<(), Error> {
+        let _connection = establish_connection();
+        let item = schema::suggest_items::table
+            .filter(schema::suggest_items::id.eq(id))
+            .first::<SuggestItem>(&_connection)?;
+        if item.status != 0 {
+            return Err(Error::BadRequest("The application is already processed".to_string()));
+        }
+        let _user = schema::users::table
+            .filter(schema::users::email.eq(&item.email))
+            .first::<User>(&_connection)?;
+
+        let notification = _connection.transaction(|| {
+            diesel::update(&item)
+                .set(schema::suggest_items::status.eq(2))
+                .execute(&_connection)?;
+            Notification::create_with(&_connection, _user.id, EventType::Rejection, Some(item.id), "Your application is rejected")
+        })?;
+        // logged once committed, on its own connection
+        crate::models::Log::create({
+            Json(crate::models::NewLogJson {
+                user_id:   request_user.id,
+                text:      "rejected an application".to_string(),
+                target_id: Some(_user.id),
+            })
+        });
+        notification.push();
+        Ok(())
+    }
+
+    pub fn get_after(status: i16, after_id: i32, limit: i64) -> Vec<SuggestItem> {
+        let _connection = establish_connection();
+        return schema::suggest_items::table
+            .filter(schema::suggest_items::status.eq(status))
+            .filter(schema::suggest_items::id.gt(after_id))
+            .order(schema::suggest_items::id.asc())
+            .limit(limit)
+            .load::<SuggestItem>(&_connection)
+            .expect("E.");
     }
 
     pub fn get_new(limit: i64, offset: i64) -> Vec<SuggestItem> {
@@ -215,7 +303,7 @@ impl SuggestItem {
         first_name:  String,
         last_name:   String,
         email:       String,
-    ) -> () {
+    ) -> SuggestItem {
         let _connection = establish_connection();
         let is_agree: bool;
         if &form.is_agree == "on" {
@@ -232,7 +320,7 @@ impl SuggestItem {
             phone:       form.phone.clone(),
             mobile:      form.mobile.clone(),
             is_agree:    is_agree,
-            address:     form.address.clone(),
+            address:     form.address.to_string(),
             created:     chrono::Utc::now().naive_utc(),
             tokens:      form.tokens.clone(),
             token_type:  0,
@@ -241,7 +329,7 @@ impl SuggestItem {
 
         let _new_suggest_item = diesel::insert_into(schema::suggest_items::table)
             .values(&form)
-            .execute(&_connection)
+            .get_result::<SuggestItem>(&_connection)
             .expect("Error saving suggest form.");
 
         let _user = schema::users::table
@@ -256,6 +344,7 @@ impl SuggestItem {
                 target_id: None,
             })
         });
+        _new_suggest_item
     }
 }
 
@@ -412,10 +501,20 @@ impl Log {
             target_id: form.target_id,
         };
 
-        let _new_suggest_item = diesel::insert_into(schema::logs::table)
+        let _log = diesel::insert_into(schema::logs::table)
             .values(&form)
-            .execute(&_connection)
+            .get_result::<Log>(&_connection)
             .expect("Error saving log.");
+        crate::websocket::publish_to_admins (
+            crate::websocket::EventType::Log,
+            _log.id,
+            serde_json::json!({
+                "user_id":   _log.user_id,
+                "text":      _log.text,
+                "target_id": _log.target_id,
+                "created":   _log.created,
+            }),
+        );
     }
 }
 
@@ -508,6 +607,51 @@ pub struct Holder {
     pub stage:   String,
     pub count2:  String,
 }
+/// `count2` is free text, so anything that is not a plain decimal counts as zero.
+const HOLDER_AMOUNT_SQL: &str = "(CASE WHEN holders.count2 ~ '^[0-9]+(\\.[0-9]+)?$' THEN CAST(holders.count2 AS DOUBLE PRECISION) ELSE 0 END)";
+
+/// The `count2` grammar of `HOLDER_AMOUNT_SQL`: digits, optionally
+/// followed by a dot and more digits.
+fn is_holder_amount(value: &str) -> bool {
+    let (whole, fraction) = match value.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (value, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    is_digits(whole) && fraction.map_or(true, is_digits)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HolderFilter {
+    pub q:     Option<String>,
+    pub stage: Option<String>,
+    pub min:   Option<f64>,
+    pub max:   Option<f64>,
+    pub sort:  Option<String>,
+    pub order: Option<String>,
+}
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct HolderStageStats {
+    #[sql_type = "Text"]
+    pub stage:   String,
+    #[sql_type = "BigInt"]
+    pub holders: i64,
+    #[sql_type = "Text"]
+    pub tokens:  String,
+}
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct TopHolder {
+    #[sql_type = "Text"]
+    pub address: String,
+    #[sql_type = "Text"]
+    pub tokens:  String,
+}
+#[derive(Debug, Serialize)]
+pub struct HolderStats {
+    pub total_holders: i64,
+    pub stages:        Vec<HolderStageStats>,
+    pub top_holders:   Vec<TopHolder>,
+}
 #[derive(Deserialize, Serialize)]
 pub struct HolderRespData {
     pub data: Vec<Holder>,
@@ -521,42 +665,135 @@ pub struct NewHolder {
     pub stage:   String,
     pub count2:  String,
 }
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HolderJson {
+    pub address: String,
+    pub count:   Option<i16>,
+    pub stage:   String,
+    pub count2:  String,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EditHolderJson {
+    pub id:     i32,
+    pub count2: String,
+    pub stage:  String,
+}
+impl HolderJson {
+    /// Checks the row and returns the address in its checksummed form.
+    pub fn validate(&self) -> Result<WalletAddress, String> {
+        let address = WalletAddress::parse(&self.address)?;
+        if self.stage.trim().is_empty() {
+            return Err("stage is empty".to_string());
+        }
+        match is_holder_amount(self.count2.trim()) {
+            true => Ok(address),
+            false => Err("count2 is not a valid amount: ".to_string() + &self.count2),
+        }
+    }
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HolderImportError {
+    pub row:     usize,
+    pub message: String,
+}
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HolderImportReport {
+    pub inserted: i32,
+    pub updated:  i32,
+    pub skipped:  i32,
+    pub errors:   Vec<HolderImportError>,
+}
 impl Holder {
-    pub fn create(form: Json<Vec<NewHolder>>) -> i16 {
+    pub fn create(form: Json<Vec<HolderJson>>) -> HolderImportReport {
+        let rows = form.into_inner()
+            .into_iter()
+            .enumerate()
+            .map(|(n, i)| (n + 1, Ok(i)))
+            .collect();
+        Holder::import(rows)
+    }
+    pub fn import_csv(data: &[u8]) -> HolderImportReport {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_reader(data);
+        let mut rows = Vec::new();
+        for (n, record) in reader.deserialize::<HolderJson>().enumerate() {
+            // the header is line 1, so the first data row is line 2
+            rows.push((n + 2, record.map_err(|err| err.to_string())));
+        }
+        Holder::import(rows)
+    }
+    pub fn import(rows: Vec<(usize, Result<HolderJson, String>)>) -> HolderImportReport {
         let _connection = establish_connection();
-        //diesel::delete (
-        //    schema::holders::table
-        //        .filter(schema::holders::count.eq(0))
-        //)
-        //.execute(&_connection)
-        //.expect("E");
-
-        for i in form.iter() {
-            //if schema::holders::table
-            //    .filter(schema::holders::address.eq(&i.address))
-            //    .select(schema::holders::id) 
-            //    .load::<i32>(&_connection)
-            //    .is_ok() {
-            //        diesel::delete (
-            //            schema::holders::table
-            //                .filter(schema::holders::address.eq(&i.address))
-            //        )
-            //        .execute(&_connection)
-            //        .expect("E");
-            //    }
-            let form = NewHolder {
-                address: i.address.clone(),
-                count:   0,
-                stage:   i.stage.clone(),
-                count2:  i.count2.clone(),
+        let mut report = HolderImportReport::default();
+
+        for (row, record) in rows.into_iter() {
+            let (i, address) = match record.and_then(|i| i.validate().map(|a| (i, a))) {
+                Ok(i) => i,
+                Err(message) => {
+                    report.errors.push(HolderImportError {
+                        row:     row,
+                        message: message,
+                    });
+                    continue;
+                },
             };
-    
-            let _new = diesel::insert_into(schema::holders::table)
-                .values(&form)
-                .execute(&_connection)
-                .expect("Error saving holder item.");
+            let address = address.to_string();
+            let stage = i.stage.trim().to_string();
+            let count2 = i.count2.trim().to_string();
+            let item_some = schema::holders::table
+                .filter(schema::holders::address.eq(&address))
+                .filter(schema::holders::stage.eq(&stage))
+                .first::<Holder>(&_connection);
+
+            if let Ok(item) = item_some {
+                if item.count2 == count2 && i.count.map_or(true, |c| c == item.count) {
+                    report.skipped += 1;
+                    continue;
+                }
+                diesel::update(&item)
+                    .set((
+                        schema::holders::count.eq(i.count.unwrap_or(item.count)),
+                        schema::holders::count2.eq(&count2),
+                    ))
+                    .execute(&_connection)
+                    .expect("Error updating holder item.");
+                report.updated += 1;
+            }
+            else {
+                let form = NewHolder {
+                    address: address,
+                    count:   i.count.unwrap_or(0),
+                    stage:   stage,
+                    count2:  count2,
+                };
+                let _new = diesel::insert_into(schema::holders::table)
+                    .values(&form)
+                    .on_conflict((schema::holders::address, schema::holders::stage))
+                    .do_nothing()
+                    .execute(&_connection)
+                    .expect("Error saving holder item.");
+                if _new > 0 {
+                    report.inserted += 1;
+                }
+                else {
+                    report.skipped += 1;
+                }
+            }
+        }
+        if report.inserted > 0 || report.updated > 0 {
+            Holder::publish_changed(0, "imported");
         }
-        return 1;
+        return report;
+    }
+    fn publish_changed(id: i32, action: &str) {
+        crate::websocket::publish_to_topic (
+            crate::websocket::Topic::Holders,
+            crate::websocket::EventType::Holders,
+            id,
+            serde_json::json!({ "action": action }),
+        );
     }
     pub fn delete(id: i32) -> () {
         let _connection = establish_connection();
@@ -566,33 +803,79 @@ impl Holder {
         )
         .execute(&_connection)
         .expect("E");
+        Holder::publish_changed(id, "deleted");
     }
-    pub fn edit(id: i32, count: String, stage: String) -> () {
+    /// Changes the amount and stage, checked as an imported row would be.
+    pub fn edit(id: i32, count: String, stage: String) -> Result<(), Error> {
         let _connection = establish_connection();
-        let item_some = schema::holders::table
-                .filter(schema::holders::id.eq(id))
-                .first::<Holder>(&_connection);
-            if item_some.is_ok() {
-                let item = item_some.expect("E.");
-                diesel::update(&item)
-                    .set((
-                        schema::holders::count2.eq(count),
-                        schema::holders::stage.eq(stage),
-                    ))
-                    .execute(&_connection)
-                    .expect("E");
-            }
-    }  
-    pub fn get(limit: i64, offset: i64) -> Vec<Holder> {
+        let item = schema::holders::table
+            .filter(schema::holders::id.eq(id))
+            .first::<Holder>(&_connection)?;
+        let form = HolderJson {
+            address: item.address.clone(),
+            count:   None,
+            stage:   stage.trim().to_string(),
+            count2:  count.trim().to_string(),
+        };
+        form.validate().map_err(Error::BadRequest)?;
+        diesel::update(&item)
+            .set((
+                schema::holders::count2.eq(form.count2),
+                schema::holders::stage.eq(form.stage),
+            ))
+            .execute(&_connection)?;
+        Holder::publish_changed(id, "edited");
+        Ok(())
+    }
+    pub fn get_after(after_id: i32, limit: i64) -> Vec<Holder> {
         let _connection = establish_connection();
         return schema::holders::table
+            .filter(schema::holders::id.gt(after_id))
             .order(schema::holders::id.asc())
             .limit(limit)
-            .offset(offset) 
             .load::<Holder>(&_connection)
             .expect("E.");
     }
-    pub fn get_list(page: i64, limit: Option<i64>) -> HolderRespData {
+    fn filtered<'a>(filter: &HolderFilter) -> schema::holders::BoxedQuery<'a, diesel::pg::Pg> {
+        let mut query = schema::holders::table.into_boxed();
+        if let Some(ref q) = filter.q {
+            let prefix = q.trim()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            query = query.filter(schema::holders::address.ilike(prefix + "%"));
+        }
+        if let Some(ref stage) = filter.stage {
+            query = query.filter(schema::holders::stage.eq(stage.clone()));
+        }
+        if let Some(min) = filter.min {
+            query = query.filter(sql::<Bool>(&(HOLDER_AMOUNT_SQL.to_string() + " >= ")).bind::<Double, _>(min));
+        }
+        if let Some(max) = filter.max {
+            query = query.filter(sql::<Bool>(&(HOLDER_AMOUNT_SQL.to_string() + " <= ")).bind::<Double, _>(max));
+        }
+        let desc = filter.order.as_deref() == Some("desc");
+        query = match (filter.sort.as_deref(), desc) {
+            (Some("address"), false) => query.order((schema::holders::address.asc(), schema::holders::id.asc())),
+            (Some("address"), true) =>  query.order((schema::holders::address.desc(), schema::holders::id.desc())),
+            (Some("stage"), false) =>   query.order((schema::holders::stage.asc(), schema::holders::id.asc())),
+            (Some("stage"), true) =>    query.order((schema::holders::stage.desc(), schema::holders::id.desc())),
+            (Some("amount"), false) =>  query.order((sql::<Double>(HOLDER_AMOUNT_SQL).asc(), schema::holders::id.asc())),
+            (Some("amount"), true) =>   query.order((sql::<Double>(HOLDER_AMOUNT_SQL).desc(), schema::holders::id.desc())),
+            (_, true) =>                query.order(schema::holders::id.desc()),
+            (_, false) =>               query.order(schema::holders::id.asc()),
+        };
+        query
+    }
+    pub fn get(filter: &HolderFilter, limit: i64, offset: i64) -> Vec<Holder> {
+        let _connection = establish_connection();
+        return Holder::filtered(filter)
+            .limit(limit)
+            .offset(offset)
+            .load::<Holder>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(filter: &HolderFilter, page: i64, limit: Option<i64>) -> HolderRespData {
         let _limit = get_limit(limit, 20);
         let mut next_page_number = 0;
         let have_next: i64;
@@ -601,13 +884,13 @@ impl Holder {
         if page > 1 {
             let step = (page - 1) * _limit;
             have_next = page * _limit + 1;
-            object_list = Holder::get(_limit.into(), step.into());
+            object_list = Holder::get(filter, _limit.into(), step.into());
         }
         else {
             have_next = _limit + 1;
-            object_list = Holder::get(_limit.into(), 0);
+            object_list = Holder::get(filter, _limit.into(), 0);
         }
-        if Holder::get(1, have_next.into()).len() > 0 {
+        if Holder::get(filter, 1, have_next.into()).len() > 0 {
             next_page_number = page + 1;
         }
         HolderRespData {
@@ -615,4 +898,60 @@ impl Holder {
             next: next_page_number,
         }
     }
-}
\ No newline at end of file
+
+    /// `top` is clamped to 1..=100.
+    pub fn get_stats(top: i64) -> HolderStats {
+        let top = top.clamp(1, 100);
+        let _connection = establish_connection();
+        let total_holders = schema::holders::table
+            .select(sql::<BigInt>("COUNT(DISTINCT holders.address)"))
+            .first::<i64>(&_connection)
+            .expect("E.");
+        let stages = sql_query(
+            "SELECT stage, COUNT(*) AS holders, COALESCE(SUM(".to_string() + HOLDER_AMOUNT_SQL + "), 0)::TEXT AS tokens
+             FROM holders GROUP BY stage ORDER BY stage"
+        )
+            .load::<HolderStageStats>(&_connection)
+            .expect("E.");
+        let top_holders = sql_query(
+            "SELECT address, SUM(".to_string() + HOLDER_AMOUNT_SQL + ")::TEXT AS tokens
+             FROM holders GROUP BY address ORDER BY SUM(" + HOLDER_AMOUNT_SQL + ") DESC, address LIMIT $1"
+        )
+            .bind::<BigInt, _>(top)
+            .load::<TopHolder>(&_connection)
+            .expect("E.");
+        HolderStats {
+            total_holders: total_holders,
+            stages:        stages,
+            top_holders:   top_holders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(count2: &str) -> HolderJson {
+        HolderJson {
+            address: "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string(),
+            count:   None,
+            stage:   "Seed".to_string(),
+            count2:  count2.to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_plain_decimals() {
+        for count2 in ["0", "15", "1500.25", " 7 "] {
+            assert!(row(count2).validate().is_ok(), "{}", count2);
+        }
+    }
+
+    #[test]
+    fn rejects_amounts_the_stats_count_as_zero() {
+        for count2 in ["inf", "NaN", "1e5", "+5", "-1", ".5", "5.", "1,5", ""] {
+            assert!(row(count2).validate().is_err(), "{}", count2);
+        }
+    }
+}
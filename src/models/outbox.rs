@@ -0,0 +1,287 @@
+use crate::schema;
+use crate::schema::email_outbox;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    PgConnection,
+};
+use diesel::sql_query;
+use diesel::sql_types::BigInt;
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, get_limit};
+use crate::errors::Error;
+use crate::mailer::{OutgoingMail, Recipient};
+use crate::models::EmailSuppression;
+
+/*
+status
+
+0. queued
+1. sent
+2. failed, will be retried at next_attempt
+3. dead - gave up after OUTBOX_MAX_ATTEMPTS
+*/
+pub const OUTBOX_QUEUED: i16 = 0;
+pub const OUTBOX_SENT: i16 = 1;
+pub const OUTBOX_RETRY: i16 = 2;
+pub const OUTBOX_DEAD: i16 = 3;
+
+pub const OUTBOX_MAX_ATTEMPTS: i32 = 8;
+/// first retry delay in seconds, doubled on every further attempt
+const OUTBOX_BACKOFF: i64 = 60;
+const OUTBOX_MAX_BACKOFF: i64 = 6 * 60 * 60;
+/// how long a claimed message stays invisible to other workers
+const OUTBOX_LEASE: i64 = 5 * 60;
+/// templates carrying one-time codes or confirm links; their bodies are
+/// never shown in the outbox listing
+const SENSITIVE_TEMPLATES: &[&str] = &["invite", "subscribe_confirm"];
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Queryable, QueryableByName, Deserialize, Serialize, Identifiable)]
+#[table_name="email_outbox"]
+pub struct EmailOutbox {
+    pub id:           i32,
+    pub template:     String,
+    pub email:        String,
+    pub name:         String,
+    pub subject:      String,
+    pub html:         String,
+    pub text:         String,
+    pub headers:      serde_json::Value,
+    pub status:       i16,
+    pub attempts:     i32,
+    pub last_error:   Option<String>,
+    pub next_attempt: chrono::NaiveDateTime,
+    pub created:      chrono::NaiveDateTime,
+    pub sent:         Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EmailOutboxRespData {
+    pub data: Vec<EmailOutbox>,
+    pub next: i64,
+}
+
+impl EmailOutbox {
+    pub fn get_status_name(status: i16) -> String {
+        return match status {
+            OUTBOX_QUEUED => "Queued".to_string(),
+            OUTBOX_SENT =>   "Sent".to_string(),
+            OUTBOX_RETRY =>  "Retrying".to_string(),
+            OUTBOX_DEAD =>   "Failed".to_string(),
+            _ =>             "Uncnown status".to_string(),
+        }
+    }
+
+    pub fn create(template: &str, mail: &OutgoingMail) -> Result<EmailOutbox, Error> {
+        EmailOutbox::create_with(&establish_connection(), template, mail)
+    }
+    pub fn create_with(_connection: &PgConnection, template: &str, mail: &OutgoingMail) -> Result<EmailOutbox, Error> {
+        EmailOutbox::create_at(_connection, template, mail, chrono::Utc::now().naive_utc())
+    }
+    /// Queues a message that must not go out before `next_attempt`. Mail
+    /// to a suppressed address is recorded as failed and never sent.
+    pub fn create_at(_connection: &PgConnection, template: &str, mail: &OutgoingMail, next_attempt: chrono::NaiveDateTime) -> Result<EmailOutbox, Error> {
+        let created = chrono::Utc::now().naive_utc();
+        let (status, last_error) = match EmailSuppression::is_suppressed_with(_connection, &mail.to.email) {
+            true => (OUTBOX_DEAD, Some("The address is suppressed".to_string())),
+            false => (OUTBOX_QUEUED, None),
+        };
+        let form = NewEmailOutbox {
+            template:     template.to_string(),
+            email:        mail.to.email.clone(),
+            name:         mail.to.name.clone(),
+            subject:      mail.subject.clone(),
+            html:         mail.html.clone(),
+            text:         mail.text.clone(),
+            headers:      serde_json::to_value(&mail.headers).expect("E."),
+            status:       status,
+            attempts:     0,
+            last_error:   last_error,
+            next_attempt: next_attempt,
+            created:      created,
+            sent:         None,
+        };
+        let _item = diesel::insert_into(schema::email_outbox::table)
+            .values(&form)
+            .get_result::<EmailOutbox>(_connection)?;
+        Ok(_item)
+    }
+
+    pub fn get_mail(&self) -> OutgoingMail {
+        OutgoingMail {
+            from_email: crate::vars::mail_from(),
+            from_name:  crate::vars::mail_from_name(),
+            to:         Recipient::new(&self.email, &self.name),
+            subject:    self.subject.clone(),
+            html:       self.html.clone(),
+            text:       self.text.clone(),
+            headers:    serde_json::from_value(self.headers.clone()).unwrap_or_default(),
+            outbox_id:  Some(self.id),
+        }
+    }
+
+    /// Claims up to `limit` due messages. Claimed rows have `next_attempt`
+    /// pushed forward by a lease, so concurrent workers skip them and a
+    /// worker that dies mid-send leaves them to be picked up again.
+    pub fn take_due(limit: i64) -> Vec<EmailOutbox> {
+        let _connection = establish_connection();
+        return sql_query(
+            "UPDATE email_outbox SET next_attempt = NOW() AT TIME ZONE 'UTC' + ($2 * INTERVAL '1 second')
+             WHERE id IN (
+                 SELECT id FROM email_outbox
+                 WHERE status IN (0, 2) AND next_attempt <= NOW() AT TIME ZONE 'UTC'
+                 ORDER BY next_attempt
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING *"
+        )
+            .bind::<BigInt, _>(limit)
+            .bind::<BigInt, _>(OUTBOX_LEASE)
+            .load::<EmailOutbox>(&_connection)
+            .expect("E.");
+    }
+
+    /// Dead-letters the messages whose address was suppressed after they
+    /// were queued and returns the rest.
+    pub fn skip_suppressed(items: Vec<EmailOutbox>) -> Vec<EmailOutbox> {
+        let _connection = establish_connection();
+        items.into_iter()
+            .filter(|item| {
+                if !EmailSuppression::is_suppressed_with(&_connection, &item.email) {
+                    return true;
+                }
+                diesel::update(item)
+                    .set((
+                        schema::email_outbox::status.eq(OUTBOX_DEAD),
+                        schema::email_outbox::last_error.eq(Some("The address is suppressed".to_string())),
+                    ))
+                    .execute(&_connection)
+                    .expect("E.");
+                false
+            })
+            .collect()
+    }
+
+    pub fn mark_sent(&self) {
+        let _connection = establish_connection();
+        diesel::update(self)
+            .set((
+                schema::email_outbox::status.eq(OUTBOX_SENT),
+                schema::email_outbox::attempts.eq(self.attempts + 1),
+                schema::email_outbox::sent.eq(Some(chrono::Utc::now().naive_utc())),
+            ))
+            .execute(&_connection)
+            .expect("E.");
+    }
+
+    /// Schedules the next attempt with exponential backoff, or dead-letters
+    /// the message once it ran out of attempts.
+    pub fn mark_failed(&self, error: String) {
+        let _connection = establish_connection();
+        let attempts = self.attempts + 1;
+        let status = match attempts >= OUTBOX_MAX_ATTEMPTS {
+            true => OUTBOX_DEAD,
+            false => OUTBOX_RETRY,
+        };
+        let delay = (OUTBOX_BACKOFF << (attempts - 1).min(16)).min(OUTBOX_MAX_BACKOFF);
+        diesel::update(self)
+            .set((
+                schema::email_outbox::status.eq(status),
+                schema::email_outbox::attempts.eq(attempts),
+                schema::email_outbox::last_error.eq(Some(error)),
+                schema::email_outbox::next_attempt.eq(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(delay)),
+            ))
+            .execute(&_connection)
+            .expect("E.");
+    }
+
+    /// Puts a failed message back in the queue.
+    pub fn retry(id: i32) -> Result<(), Error> {
+        let _connection = establish_connection();
+        let _item = schema::email_outbox::table
+            .filter(schema::email_outbox::id.eq(id))
+            .first::<EmailOutbox>(&_connection)?;
+        if _item.status == OUTBOX_SENT {
+            return Err(Error::BadRequest("The email is already sent".to_string()));
+        }
+        diesel::update(&_item)
+            .set((
+                schema::email_outbox::status.eq(OUTBOX_QUEUED),
+                schema::email_outbox::attempts.eq(0),
+                schema::email_outbox::next_attempt.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&_connection)?;
+        Ok(())
+    }
+
+    /// The message as admins may see it, with the body of sensitive
+    /// templates hidden.
+    pub fn redacted(mut self) -> EmailOutbox {
+        if SENSITIVE_TEMPLATES.contains(&self.template.as_str()) {
+            self.html = REDACTED.to_string();
+            self.text = REDACTED.to_string();
+        }
+        self
+    }
+
+    pub fn get(status: Option<i16>, limit: i64, offset: i64) -> Vec<EmailOutbox> {
+        let _connection = establish_connection();
+        let mut query = schema::email_outbox::table
+            .order(schema::email_outbox::id.desc())
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(status) = status {
+            query = query.filter(schema::email_outbox::status.eq(status));
+        }
+        return query
+            .load::<EmailOutbox>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(status: Option<i16>, page: i64, limit: Option<i64>) -> EmailOutboxRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<EmailOutbox>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = EmailOutbox::get(status, _limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = EmailOutbox::get(status, _limit.into(), 0);
+        }
+        if EmailOutbox::get(status, 1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        EmailOutboxRespData {
+            data: object_list.into_iter().map(EmailOutbox::redacted).collect(),
+            next: next_page_number,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="email_outbox"]
+pub struct NewEmailOutbox {
+    pub template:     String,
+    pub email:        String,
+    pub name:         String,
+    pub subject:      String,
+    pub html:         String,
+    pub text:         String,
+    pub headers:      serde_json::Value,
+    pub status:       i16,
+    pub attempts:     i32,
+    pub last_error:   Option<String>,
+    pub next_attempt: chrono::NaiveDateTime,
+    pub created:      chrono::NaiveDateTime,
+    pub sent:         Option<chrono::NaiveDateTime>,
+}
@@ -0,0 +1,303 @@
+use crate::schema;
+use crate::schema::payments;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+    PgConnection,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, get_limit};
+use crate::errors::Error;
+use actix_web::web::Json;
+use crate::models::{SuggestItem, User, Notification};
+use crate::websocket::EventType;
+
+/*
+status
+
+0. awaiting payment
+1. pending confirmation (tx hash is recorded)
+2. confirmed
+3. expired
+*/
+pub const PAYMENT_AWAITING: i16 = 0;
+pub const PAYMENT_PENDING: i16 = 1;
+pub const PAYMENT_CONFIRMED: i16 = 2;
+pub const PAYMENT_EXPIRED: i16 = 3;
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct Payment {
+    pub id:              i32,
+    pub suggest_item_id: i32,
+    pub user_id:         i32,
+    pub amount:          String,
+    pub currency:        String,
+    pub address:         String,
+    pub tx_hash:         Option<String>,
+    pub status:          i16,
+    pub created:         chrono::NaiveDateTime,
+    pub expires:         chrono::NaiveDateTime,
+    pub confirmed:       Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PaymentRespData {
+    pub data: Vec<Payment>,
+    pub next: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PaymentTxJson {
+    pub id:      i32,
+    pub tx_hash: String,
+}
+
+impl Payment {
+    pub fn get_status_name(status: i16) -> String {
+        return match status {
+            PAYMENT_AWAITING =>  "Awaiting payment".to_string(),
+            PAYMENT_PENDING =>   "Pending confirmation".to_string(),
+            PAYMENT_CONFIRMED => "Confirmed".to_string(),
+            PAYMENT_EXPIRED =>   "Expired".to_string(),
+            _ =>                 "Uncnown status".to_string(),
+        }
+    }
+
+    pub fn create(item: &SuggestItem, amount: String) -> Result<Payment, Error> {
+        let _user = User::get_user_with_email(item.email.clone())?;
+        Payment::create_with(&establish_connection(), item, _user.id, amount)
+    }
+    /// Opens the payment of an approved application. Re-approving resets an
+    /// awaiting or expired payment; one that is already paid or pending
+    /// confirmation is refused.
+    pub fn create_with(_connection: &PgConnection, item: &SuggestItem, user_id: i32, amount: String) -> Result<Payment, Error> {
+        let amount = amount.trim().to_string();
+        if amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit() || c == '.')
+            || amount.matches('.').count() > 1 || amount.trim_matches(|c| c == '0' || c == '.').is_empty() {
+            return Err(Error::BadRequest("Invalid payment amount".to_string()));
+        }
+        let address = crate::vars::payment_address()
+            .ok_or_else(|| Error::InternalServerError("PAYMENT_ADDRESS is not set".to_string()))?;
+        let existing = schema::payments::table
+            .filter(schema::payments::suggest_item_id.eq(item.id))
+            .select(schema::payments::status)
+            .for_update()
+            .first::<i16>(_connection)
+            .ok();
+        if let Some(status) = existing {
+            if status == PAYMENT_PENDING || status == PAYMENT_CONFIRMED {
+                return Err(Error::BadRequest("Payment is already ".to_string() + &Payment::get_status_name(status)));
+            }
+        }
+        let created = chrono::Utc::now().naive_utc();
+        let form = NewPayment {
+            suggest_item_id: item.id,
+            user_id:         user_id,
+            amount:          amount,
+            currency:        crate::vars::payment_currency(),
+            address:         address,
+            tx_hash:         None,
+            status:          PAYMENT_AWAITING,
+            created:         created,
+            expires:         created + chrono::Duration::hours(crate::vars::payment_ttl_hours()),
+            confirmed:       None,
+        };
+
+        // re-approving an application resets its payment instead of adding a second one
+        let _payment = diesel::insert_into(schema::payments::table)
+            .values(&form)
+            .on_conflict(schema::payments::suggest_item_id)
+            .do_update()
+            .set((
+                schema::payments::amount.eq(&form.amount),
+                schema::payments::currency.eq(&form.currency),
+                schema::payments::address.eq(&form.address),
+                schema::payments::tx_hash.eq(None::<String>),
+                schema::payments::status.eq(PAYMENT_AWAITING),
+                schema::payments::created.eq(form.created),
+                schema::payments::expires.eq(form.expires),
+                schema::payments::confirmed.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .get_result::<Payment>(_connection)?;
+        Ok(_payment)
+    }
+
+    pub fn get_item(id: i32) -> Result<Payment, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::payments::table
+            .filter(schema::payments::id.eq(id))
+            .first::<Payment>(&_connection)?);
+    }
+
+    pub fn get_for_application(suggest_item_id: i32) -> Option<Payment> {
+        let _connection = establish_connection();
+        return schema::payments::table
+            .filter(schema::payments::suggest_item_id.eq(suggest_item_id))
+            .first::<Payment>(&_connection)
+            .ok();
+    }
+
+    /// The payment row locked for the rest of the transaction, so the chain
+    /// watcher cannot change its status in between.
+    fn lock(&self, _connection: &PgConnection) -> Result<Payment, Error> {
+        Ok(schema::payments::table
+            .filter(schema::payments::id.eq(self.id))
+            .for_update()
+            .first::<Payment>(_connection)?)
+    }
+
+    pub fn record_tx(&self, request_user: &User, tx_hash: String) -> Result<(), Error> {
+        if !request_user.is_admin() {
+            return Err(Error::BadRequest("403".to_string()));
+        }
+        let tx_hash = tx_hash.trim().to_lowercase();
+        if !is_tx_hash(&tx_hash) {
+            return Err(Error::BadRequest("Invalid transaction hash".to_string()));
+        }
+        let _connection = establish_connection();
+        let notifications = _connection.transaction(|| {
+            let current = self.lock(&_connection)?;
+            if current.status != PAYMENT_AWAITING && current.status != PAYMENT_PENDING {
+                return Err(Error::BadRequest("Payment is already ".to_string() + &Payment::get_status_name(current.status)));
+            }
+            diesel::update(&current)
+                .set((
+                    schema::payments::tx_hash.eq(Some(tx_hash.clone())),
+                    schema::payments::status.eq(PAYMENT_PENDING),
+                ))
+                .execute(&_connection)?;
+            crate::models::Log::create({
+                Json(crate::models::NewLogJson {
+                    user_id:   request_user.id,
+                    text:      "recorded a payment transaction".to_string(),
+                    target_id: Some(self.user_id),
+                })
+            });
+            // the watcher may have stored the transfer already
+            crate::models::ChainEvent::confirm_payments(&_connection, vec![tx_hash.clone()])
+        })?;
+        for i in notifications.iter() {
+            i.push();
+        }
+        Ok(())
+    }
+
+    pub fn confirm(&self, request_user: &User) -> Result<(), Error> {
+        if !request_user.is_admin() {
+            return Err(Error::BadRequest("403".to_string()));
+        }
+        let _connection = establish_connection();
+        let notification = _connection.transaction(|| {
+            let current = self.lock(&_connection)?;
+            if current.status != PAYMENT_PENDING {
+                return Err(Error::BadRequest("Payment has no transaction to confirm".to_string()));
+            }
+            diesel::update(&current)
+                .set((
+                    schema::payments::status.eq(PAYMENT_CONFIRMED),
+                    schema::payments::confirmed.eq(Some(chrono::Utc::now().naive_utc())),
+                ))
+                .execute(&_connection)?;
+            crate::models::Log::create({
+                Json(crate::models::NewLogJson {
+                    user_id:   request_user.id,
+                    text:      "confirmed a payment".to_string(),
+                    target_id: Some(self.user_id),
+                })
+            });
+            self.notify_confirmed(&_connection)
+        })?;
+        notification.push();
+        Ok(())
+    }
+
+    /// Stores the "payment confirmed" notification for the payer.
+    pub fn notify_confirmed(&self, _connection: &PgConnection) -> Result<Notification, Error> {
+        Notification::create_with(_connection, self.user_id, EventType::PaymentConfirmed, Some(self.suggest_item_id), "Your payment is confirmed")
+    }
+
+    pub fn expire_overdue() -> usize {
+        let _connection = establish_connection();
+        return diesel::update (
+            schema::payments::table
+                .filter(schema::payments::status.eq(PAYMENT_AWAITING))
+                .filter(schema::payments::expires.lt(chrono::Utc::now().naive_utc()))
+        )
+        .set(schema::payments::status.eq(PAYMENT_EXPIRED))
+        .execute(&_connection)
+        .expect("E.");
+    }
+
+    pub fn get(status: Option<i16>, limit: i64, offset: i64) -> Vec<Payment> {
+        let _connection = establish_connection();
+        let mut query = schema::payments::table
+            .order(schema::payments::created.desc())
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(status) = status {
+            query = query.filter(schema::payments::status.eq(status));
+        }
+        return query
+            .load::<Payment>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_list(status: Option<i16>, page: i64, limit: Option<i64>) -> PaymentRespData {
+        let _limit = get_limit(limit, 20);
+        let mut next_page_number = 0;
+        let have_next: i64;
+        let object_list: Vec<Payment>;
+
+        if page > 1 {
+            let step = (page - 1) * _limit;
+            have_next = page * _limit + 1;
+            object_list = Payment::get(status, _limit.into(), step.into());
+        }
+        else {
+            have_next = _limit + 1;
+            object_list = Payment::get(status, _limit.into(), 0);
+        }
+        if Payment::get(status, 1, have_next.into()).len() > 0 {
+            next_page_number = page + 1;
+        }
+        PaymentRespData {
+            data: object_list,
+            next: next_page_number,
+        }
+    }
+
+    pub fn get_for_user(user_id: i32) -> Vec<Payment> {
+        let _connection = establish_connection();
+        return schema::payments::table
+            .filter(schema::payments::user_id.eq(user_id))
+            .order(schema::payments::created.desc())
+            .load::<Payment>(&_connection)
+            .expect("E.");
+    }
+}
+
+/// `0x` and 64 hex digits.
+fn is_tx_hash(value: &str) -> bool {
+    value.len() == 66
+        && value.starts_with("0x")
+        && value[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="payments"]
+pub struct NewPayment {
+    pub suggest_item_id: i32,
+    pub user_id:         i32,
+    pub amount:          String,
+    pub currency:        String,
+    pub address:         String,
+    pub tx_hash:         Option<String>,
+    pub status:          i16,
+    pub created:         chrono::NaiveDateTime,
+    pub expires:         chrono::NaiveDateTime,
+    pub confirmed:       Option<chrono::NaiveDateTime>,
+}
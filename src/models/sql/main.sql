@@ -9,6 +9,7 @@ CREATE TABLE users (
     image      VARCHAR(500),
     created    TIMESTAMP NOT NULL,
     uuid       VARCHAR(100) NOT NULL,
+    locale     VARCHAR(10) NOT NULL DEFAULT 'en',
     UNIQUE(email)
 );
 
@@ -19,16 +20,62 @@ CREATE TABLE email_verification_token (
     created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
 ); 
 
-CREATE TABLE wallets (
+CREATE TABLE new_wallets (
     id       SERIAL PRIMARY KEY,
     user_id  INT NOT NULL,
-    link     VARCHAR(100) NOT NULL
+    link     VARCHAR(100) NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE(user_id, link)
 ); 
+-- several users may claim a wallet, only one may prove it
+CREATE UNIQUE INDEX new_wallets_verified ON new_wallets (link) WHERE verified;
 
-CREATE TABLE white_lists (
+CREATE TABLE wallet_challenges (
+    id       SERIAL PRIMARY KEY,
+    user_id  INT NOT NULL,
+    address  VARCHAR(42) NOT NULL,
+    nonce    VARCHAR(64) NOT NULL,
+    expires  TIMESTAMP NOT NULL,
+    UNIQUE(user_id, address)
+);
+
+-- spent SIWE nonces, kept until they expire
+CREATE TABLE siwe_nonces (
+    nonce    VARCHAR(88) PRIMARY KEY,
+    expires  TIMESTAMP NOT NULL
+);
+
+CREATE TABLE ws_tickets (
+    ticket   VARCHAR(32) PRIMARY KEY,
+    user_id  INT NOT NULL,
+    expires  TIMESTAMP NOT NULL
+);
+
+CREATE TABLE new_white_lists (
     id         SERIAL PRIMARY KEY,
     user_id    INT NOT NULL,
-    token_type SMALLINT NOT NULL
+    token_type SMALLINT NOT NULL,
+    allocation VARCHAR(100) NOT NULL DEFAULT '0',
+    UNIQUE(user_id, token_type)
+);
+
+CREATE TABLE white_list_trees (
+    id         SERIAL PRIMARY KEY,
+    token_type SMALLINT NOT NULL,
+    root       VARCHAR(66) NOT NULL,
+    entries    JSONB NOT NULL,
+    created    TIMESTAMP NOT NULL
+);
+
+-- the result of the last white list reconciliation
+CREATE TABLE white_list_mismatches (
+    id         SERIAL PRIMARY KEY,
+    kind       VARCHAR(50) NOT NULL,
+    user_id    INT,
+    email      VARCHAR(100) NOT NULL,
+    token_type SMALLINT NOT NULL,
+    address    VARCHAR(100),
+    checked    TIMESTAMP NOT NULL
 );
 
 CREATE TABLE logs (
@@ -69,5 +116,149 @@ CREATE TABLE holders (
     address   VARCHAR(100) NOT NULL,
     count     SMALLINT NOT NULL,
     stage     VARCHAR(50) NOT NULL,
-    count2    VARCHAR(50) NOT NULL
-);
\ No newline at end of file
+    count2    VARCHAR(50) NOT NULL,
+    UNIQUE(address, stage)
+);
+
+CREATE TABLE payments (
+    id              SERIAL PRIMARY KEY,
+    suggest_item_id INT NOT NULL,
+    user_id         INT NOT NULL,
+    amount          VARCHAR(100) NOT NULL,
+    currency        VARCHAR(20) NOT NULL,
+    address         VARCHAR(100) NOT NULL,
+    tx_hash         VARCHAR(100),
+    status          SMALLINT NOT NULL,
+    created         TIMESTAMP NOT NULL,
+    expires         TIMESTAMP NOT NULL,
+    confirmed       TIMESTAMP,
+    UNIQUE(suggest_item_id),
+    UNIQUE(tx_hash)
+);
+
+CREATE TABLE chain_events (
+    id           SERIAL PRIMARY KEY,
+    contract     VARCHAR(42) NOT NULL,
+    kind         VARCHAR(20) NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash   VARCHAR(66) NOT NULL,
+    tx_hash      VARCHAR(66) NOT NULL,
+    log_index    INT NOT NULL,
+    from_address VARCHAR(42) NOT NULL,
+    to_address   VARCHAR(42) NOT NULL,
+    amount       VARCHAR(100) NOT NULL,
+    created      TIMESTAMP NOT NULL,
+    UNIQUE(tx_hash, log_index)
+);
+
+CREATE TABLE chain_cursors (
+    contract     VARCHAR(42) PRIMARY KEY,
+    block_number BIGINT NOT NULL,
+    block_hash   VARCHAR(66)
+);
+
+CREATE TABLE email_outbox (
+    id           SERIAL PRIMARY KEY,
+    template     VARCHAR(100) NOT NULL,
+    email        VARCHAR(100) NOT NULL,
+    name         VARCHAR(200) NOT NULL,
+    subject      TEXT NOT NULL,
+    html         TEXT NOT NULL,
+    text         TEXT NOT NULL,
+    headers      JSONB NOT NULL,
+    status       SMALLINT NOT NULL,
+    attempts     INT NOT NULL,
+    last_error   TEXT,
+    next_attempt TIMESTAMP NOT NULL,
+    created      TIMESTAMP NOT NULL,
+    sent         TIMESTAMP
+);
+CREATE INDEX email_outbox_due ON email_outbox (status, next_attempt);
+
+-- a row is either an admin (user_id) or an outside address (email, name)
+CREATE TABLE notification_subscriptions (
+    id      SERIAL PRIMARY KEY,
+    user_id INT,
+    email   VARCHAR(100),
+    name    VARCHAR(200),
+    event   VARCHAR(50) NOT NULL,
+    channel VARCHAR(20) NOT NULL,
+    CHECK ((user_id IS NULL) <> (email IS NULL)),
+    UNIQUE(user_id, event, channel),
+    UNIQUE(email, event, channel)
+);
+
+-- the recipients that were hard-coded before subscriptions existed
+INSERT INTO notification_subscriptions (email, name, event, channel) VALUES
+    ('Beatrice.OBrien@justlaw.com', 'Beatrice O''Brien', 'new_user', 'email'),
+    ('Beatrice.OBrien@justlaw.com', 'Beatrice O''Brien', 'new_application', 'email'),
+    ('Beatrice.OBrien@justlaw.com', 'Beatrice O''Brien', 'new_subscriber', 'email'),
+    ('colin@bjustcoin.com', 'Colin Martin', 'new_subscriber', 'email');
+
+CREATE TABLE subscribers (
+    id           SERIAL PRIMARY KEY,
+    email        VARCHAR(100) NOT NULL,
+    status       SMALLINT NOT NULL,
+    ip           VARCHAR(45),
+    created      TIMESTAMP NOT NULL,
+    confirmed    TIMESTAMP,
+    unsubscribed TIMESTAMP,
+    requested    TIMESTAMP NOT NULL,
+    UNIQUE(email)
+);
+
+CREATE TABLE campaigns (
+    id        SERIAL PRIMARY KEY,
+    user_id   INT NOT NULL,
+    subject   VARCHAR(300) NOT NULL,
+    html      TEXT NOT NULL,
+    segment   VARCHAR(20) NOT NULL,
+    stage     SMALLINT,
+    status    SMALLINT NOT NULL,
+    scheduled TIMESTAMP NOT NULL,
+    created   TIMESTAMP NOT NULL,
+    total     INT NOT NULL,
+    attempts  INT NOT NULL DEFAULT 0,
+    started   TIMESTAMP
+);
+
+CREATE TABLE campaign_recipients (
+    id          SERIAL PRIMARY KEY,
+    campaign_id INT NOT NULL,
+    email       VARCHAR(100) NOT NULL,
+    name        VARCHAR(200) NOT NULL,
+    outbox_id   INT NOT NULL,
+    UNIQUE(campaign_id, email)
+);
+
+CREATE TABLE email_events (
+    id            SERIAL PRIMARY KEY,
+    outbox_id     INT,
+    email         VARCHAR(100) NOT NULL,
+    event         VARCHAR(30) NOT NULL,
+    reason        TEXT,
+    sg_event_id   VARCHAR(100),
+    sg_message_id VARCHAR(200),
+    created       TIMESTAMP NOT NULL,
+    UNIQUE(sg_event_id)
+);
+CREATE INDEX email_events_email ON email_events (email);
+
+CREATE TABLE email_suppressions (
+    id      SERIAL PRIMARY KEY,
+    email   VARCHAR(100) NOT NULL,
+    reason  VARCHAR(30) NOT NULL,
+    created TIMESTAMP NOT NULL,
+    UNIQUE(email)
+);
+
+CREATE TABLE notifications (
+    id        SERIAL PRIMARY KEY,
+    user_id   INT NOT NULL,
+    types     VARCHAR(30) NOT NULL,
+    target_id INT,
+    text      VARCHAR(200) NOT NULL,
+    is_read   BOOLEAN NOT NULL DEFAULT false,
+    created   TIMESTAMP NOT NULL
+);
+CREATE INDEX notifications_user ON notifications (user_id, id);
@@ -0,0 +1,216 @@
+use crate::schema;
+use crate::schema::subscribers;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, sign_token, verify_token};
+use crate::errors::Error;
+
+/*
+status
+
+0. waiting for confirmation
+1. confirmed
+2. unsubscribed
+*/
+pub const SUBSCRIBER_PENDING: i16 = 0;
+pub const SUBSCRIBER_CONFIRMED: i16 = 1;
+pub const SUBSCRIBER_UNSUBSCRIBED: i16 = 2;
+
+/// How long a confirmation link stays valid.
+const CONFIRM_TTL_HOURS: i64 = 48;
+/// A confirmation email is sent to an address at most once in this time.
+const RESEND_MINUTES: i64 = 5;
+/// How many addresses one IP may request confirmations for in an hour.
+const MAX_REQUESTS_PER_IP: i64 = 5;
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct Subscriber {
+    pub id:           i32,
+    pub email:        String,
+    pub status:       i16,
+    pub ip:           Option<String>,
+    pub created:      chrono::NaiveDateTime,
+    pub confirmed:    Option<chrono::NaiveDateTime>,
+    pub unsubscribed: Option<chrono::NaiveDateTime>,
+    pub requested:    chrono::NaiveDateTime,
+}
+
+impl Subscriber {
+    pub fn get_status_name(status: i16) -> String {
+        return match status {
+            SUBSCRIBER_PENDING =>      "Waiting for confirmation".to_string(),
+            SUBSCRIBER_CONFIRMED =>    "Confirmed".to_string(),
+            SUBSCRIBER_UNSUBSCRIBED => "Unsubscribed".to_string(),
+            _ =>                       "Uncnown status".to_string(),
+        }
+    }
+
+    fn token_value(&self) -> String {
+        self.id.to_string() + ":" + &self.email
+    }
+    /// Confirmation tokens also sign when they were issued, so only the
+    /// link of the latest request works and it expires.
+    fn confirm_value(&self, issued: i64) -> String {
+        self.token_value() + ":" + &issued.to_string()
+    }
+    pub fn confirm_url(&self) -> String {
+        let issued = self.requested.timestamp();
+        format!(
+            "{}/confirm_subscription/?id={}&issued={}&token={}",
+            crate::vars::api_url(), self.id, issued, sign_token("subscribe", &self.confirm_value(issued)),
+        )
+    }
+    pub fn unsubscribe_url(&self) -> String {
+        format!(
+            "{}/unsubscribe/?id={}&token={}",
+            crate::vars::api_url(), self.id, sign_token("unsubscribe", &self.token_value()),
+        )
+    }
+    /// RFC 2369 / RFC 8058 headers for one-click unsubscribe.
+    pub fn unsubscribe_headers(&self) -> Vec<(String, String)> {
+        vec![
+            ("List-Unsubscribe".to_string(), "<".to_string() + &self.unsubscribe_url() + ">"),
+            ("List-Unsubscribe-Post".to_string(), "List-Unsubscribe=One-Click".to_string()),
+        ]
+    }
+
+    pub fn get_item(id: i32) -> Result<Subscriber, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::subscribers::table
+            .filter(schema::subscribers::id.eq(id))
+            .first::<Subscriber>(&_connection)?);
+    }
+
+    pub fn get_with_email(email: &str) -> Option<Subscriber> {
+        let _connection = establish_connection();
+        return schema::subscribers::table
+            .filter(schema::subscribers::email.eq(email.trim().to_lowercase()))
+            .first::<Subscriber>(&_connection)
+            .ok();
+    }
+
+    /// Records a subscription request. Confirmed subscribers are returned
+    /// as they are; anyone else goes back to waiting for confirmation.
+    /// Requests are limited per address and per IP, since anyone can make
+    /// us email any address.
+    pub fn create(email: &str, ip: Option<String>) -> Result<Subscriber, Error> {
+        let email = email.trim().to_lowercase();
+        if email.len() > 100 || !email.contains('@') {
+            return Err(Error::BadRequest("Invalid email".to_string()));
+        }
+        let _connection = establish_connection();
+        let now = chrono::Utc::now().naive_utc();
+        if let Some(ip) = ip.as_ref() {
+            let count = schema::subscribers::table
+                .filter(schema::subscribers::ip.eq(ip))
+                .filter(schema::subscribers::requested.gt(now - chrono::Duration::hours(1)))
+                .count()
+                .get_result::<i64>(&_connection)?;
+            if count >= MAX_REQUESTS_PER_IP {
+                return Err(Error::BadRequest("Too many requests, try again later".to_string()));
+            }
+        }
+        if let Some(item) = Subscriber::get_with_email(&email) {
+            if item.status == SUBSCRIBER_CONFIRMED {
+                return Ok(item);
+            }
+            if item.requested > now - chrono::Duration::minutes(RESEND_MINUTES) {
+                return Err(Error::BadRequest("Too many requests, try again later".to_string()));
+            }
+            return Ok(diesel::update(&item)
+                .set((
+                    schema::subscribers::status.eq(SUBSCRIBER_PENDING),
+                    schema::subscribers::ip.eq(ip),
+                    schema::subscribers::requested.eq(now),
+                ))
+                .get_result::<Subscriber>(&_connection)?);
+        }
+        let form = NewSubscriber {
+            email:        email,
+            status:       SUBSCRIBER_PENDING,
+            ip:           ip,
+            created:      now,
+            confirmed:    None,
+            unsubscribed: None,
+            requested:    now,
+        };
+        Ok(diesel::insert_into(schema::subscribers::table)
+            .values(&form)
+            .get_result::<Subscriber>(&_connection)?)
+    }
+
+    /// Returns true if this call is what confirmed the subscription.
+    /// `issued` is the unix time signed into the link; links older than
+    /// `CONFIRM_TTL_HOURS` or issued before an unsubscribe are refused.
+    pub fn confirm(id: i32, issued: i64, token: &str) -> Result<(Subscriber, bool), Error> {
+        let item = Subscriber::get_item(id)?;
+        if !verify_token("subscribe", &item.confirm_value(issued), token) {
+            return Err(Error::BadRequest("Invalid confirmation link".to_string()));
+        }
+        if item.status == SUBSCRIBER_CONFIRMED {
+            return Ok((item, false));
+        }
+        let now = chrono::Utc::now().naive_utc();
+        if issued != item.requested.timestamp()
+            || item.requested < now - chrono::Duration::hours(CONFIRM_TTL_HOURS)
+            || item.unsubscribed.map(|u| u >= item.requested).unwrap_or(false) {
+            return Err(Error::BadRequest("The confirmation link has expired".to_string()));
+        }
+        let _connection = establish_connection();
+        let item = diesel::update(&item)
+            .set((
+                schema::subscribers::status.eq(SUBSCRIBER_CONFIRMED),
+                schema::subscribers::confirmed.eq(Some(now)),
+            ))
+            .get_result::<Subscriber>(&_connection)?;
+        Ok((item, true))
+    }
+
+    pub fn unsubscribe(id: i32, token: &str) -> Result<(), Error> {
+        let item = Subscriber::get_item(id)?;
+        if !verify_token("unsubscribe", &item.token_value(), token) {
+            return Err(Error::BadRequest("Invalid unsubscribe link".to_string()));
+        }
+        if item.status == SUBSCRIBER_UNSUBSCRIBED {
+            return Ok(());
+        }
+        let _connection = establish_connection();
+        diesel::update(&item)
+            .set((
+                schema::subscribers::status.eq(SUBSCRIBER_UNSUBSCRIBED),
+                schema::subscribers::unsubscribed.eq(Some(chrono::Utc::now().naive_utc())),
+            ))
+            .execute(&_connection)?;
+        Ok(())
+    }
+
+    /// Confirmed subscribers in id order, for exports.
+    pub fn get_after(after_id: i32, limit: i64) -> Vec<Subscriber> {
+        let _connection = establish_connection();
+        return schema::subscribers::table
+            .filter(schema::subscribers::status.eq(SUBSCRIBER_CONFIRMED))
+            .filter(schema::subscribers::id.gt(after_id))
+            .order(schema::subscribers::id.asc())
+            .limit(limit)
+            .load::<Subscriber>(&_connection)
+            .expect("E.");
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="subscribers"]
+pub struct NewSubscriber {
+    pub email:        String,
+    pub status:       i16,
+    pub ip:           Option<String>,
+    pub created:      chrono::NaiveDateTime,
+    pub confirmed:    Option<chrono::NaiveDateTime>,
+    pub unsubscribed: Option<chrono::NaiveDateTime>,
+    pub requested:    chrono::NaiveDateTime,
+}
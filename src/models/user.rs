@@ -10,10 +10,12 @@ use crate::diesel::{
     QueryDsl,
     ExpressionMethods,
     RunQueryDsl,
+    JoinOnDsl,
     Connection,
+    PgConnection,
 };
 use serde::{Serialize, Deserialize};
-use crate::utils::{establish_connection, get_limit};
+use crate::utils::{establish_connection, get_limit, WalletAddress};
 use crate::errors::Error;
 use actix_web::web::Json;
 use crate::views::{NewUserJson, AuthResp, AuthRespData};
@@ -38,17 +40,7 @@ pub struct UserData {
 
 impl UserData {
     pub fn get_user_wallets(&self) -> Vec<UserWallet> {
-        let _connection = establish_connection();
-        return schema::suggest_items::table 
-            .filter(schema::suggest_items::email.eq(self.email.clone()))
-            .filter(schema::suggest_items::status.eq(1))
-            .select((
-                schema::suggest_items::address,
-                schema::suggest_items::tokens,
-                schema::suggest_items::token_type
-            ))
-            .load::<crate::models::UserWallet>(&_connection)
-            .expect("E.");
+        UserWallet::get_for(self.id)
     } 
 }
 
@@ -65,6 +57,7 @@ pub struct User {
     pub image:      Option<String>,
     pub created:    chrono::NaiveDateTime,
     pub uuid:       String,
+    pub locale:     String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -81,17 +74,7 @@ pub struct SmallUser {
 
 impl User {
     pub fn get_user_wallets(&self) -> Vec<UserWallet> {
-        let _connection = establish_connection();
-        return schema::suggest_items::table 
-            .filter(schema::suggest_items::email.eq(self.email.clone()))
-            .filter(schema::suggest_items::status.eq(1))
-            .select(( 
-                schema::suggest_items::address,
-                schema::suggest_items::tokens,
-                schema::suggest_items::token_type
-            ))
-            .load::<crate::models::UserWallet>(&_connection)
-            .expect("E.");
+        UserWallet::get_for(self.id)
     } 
     pub fn get_small_users() -> Vec<SmallUser> {
         let _connection = establish_connection();
@@ -107,6 +90,15 @@ impl User {
             .load::<SmallUser>(&_connection)
             .expect("E.");
     }
+    pub fn get_after(after_id: i32, limit: i64) -> Vec<User> {
+        let _connection = establish_connection();
+        return schema::users::table
+            .filter(schema::users::id.gt(after_id))
+            .order(schema::users::id.asc())
+            .limit(limit)
+            .load::<User>(&_connection)
+            .expect("E.");
+    }
     pub fn get_users(limit: i64, offset: i64) -> Vec<AuthResp> {
         let _connection = establish_connection();
         let _users = schema::users::table
@@ -402,24 +394,25 @@ impl User {
             });
         }))
     } 
+    /// Blocks the user. Their applications and white list rows are kept so
+    /// `delete_user_block` restores them as they were; blocked users are
+    /// left out of published white lists instead.
     pub fn create_user_block(&self, user_id: i32) -> Result<(), Error> {
         if !self.is_admin() {
             return Err(Error::BadRequest("403".to_string()));
         }
         let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::perm.eq(USERISBLOCK))
-                .execute(&_connection);
-            let _k = NewWhiteList::delete_all(user_id);
-            crate::models::Log::create({
-                Json(crate::models::NewLogJson {
-                    user_id:   self.id,
-                    text:      "blocked the user".to_string(),
-                    target_id: Some(user_id),
-                })
-            });
-        }))
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(schema::users::perm.eq(USERISBLOCK))
+            .execute(&_connection)?;
+        crate::models::Log::create({
+            Json(crate::models::NewLogJson {
+                user_id:   self.id,
+                text:      "blocked the user".to_string(),
+                target_id: Some(user_id),
+            })
+        });
+        Ok(())
     }
     pub fn delete_user_block(&self, user_id: i32) -> Result<(), Error> {
         if !self.is_admin() {
@@ -502,6 +495,31 @@ impl User {
         }))
     }
 
+    pub fn set_locale(&self, locale: &str) -> Result<(), Error> {
+        let locale = crate::i18n::normalize(locale)
+            .ok_or_else(|| Error::BadRequest("Unknown locale: ".to_string() + locale))?;
+        let _connection = establish_connection();
+        diesel::update(self)
+            .set(schema::users::locale.eq(locale))
+            .execute(&_connection)?;
+        Ok(())
+    }
+    pub fn get_recipient(&self) -> crate::mailer::Recipient {
+        crate::mailer::Recipient::new(&self.email, &(self.first_name.clone() + " " + &self.last_name))
+            .with_locale(&self.locale)
+    }
+    pub fn get_user(id: i32) -> Result<User, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::users::table
+            .filter(schema::users::id.eq(id))
+            .first::<User>(&_connection)?);
+    }
+    pub fn get_user_with_uuid(uuid: &str) -> Result<User, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::users::table
+            .filter(schema::users::uuid.eq(uuid))
+            .first::<User>(&_connection)?);
+    }
     pub fn get_user_with_email(email: String) -> Result<User, Error> { 
         let _connection = establish_connection();
         
@@ -509,7 +527,7 @@ impl User {
             .filter(schema::users::email.eq(email))
             .first::<User>(&_connection)?);
     }
-    pub fn create(form: Json<NewUserJson>) -> User {
+    pub fn create(form: Json<NewUserJson>, locale: String) -> User {
         use rand::Rng;
 
         let id = rand::thread_rng().gen::<[u8; 32]>().to_vec();
@@ -525,6 +543,7 @@ impl User {
             image:      None,
             created:    chrono::Utc::now().naive_utc(),
             uuid:       uuid,
+            locale:     locale,
         };
 
         let _new_user = diesel::insert_into(schema::users::table)
@@ -575,6 +594,7 @@ pub struct NewUser {
     pub image:      Option<String>,
     pub created:    chrono::NaiveDateTime,
     pub uuid:       String,
+    pub locale:     String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -605,33 +625,131 @@ pub struct UserWallet {
     pub token_type: i16,
 } 
 
+impl UserWallet {
+    /// The white list rows of a user, each on their primary wallet: the
+    /// oldest verified one, or the oldest one when none is verified yet.
+    pub fn get_for(user_id: i32) -> Vec<UserWallet> {
+        let _connection = establish_connection();
+        let wallet = schema::new_wallets::table
+            .filter(schema::new_wallets::user_id.eq(user_id))
+            .order((schema::new_wallets::verified.desc(), schema::new_wallets::id.asc()))
+            .select(schema::new_wallets::link)
+            .first::<String>(&_connection);
+        let wallet = match wallet {
+            Ok(link) => link,
+            Err(_) => return Vec::new(),
+        };
+        return schema::new_white_lists::table
+            .filter(schema::new_white_lists::user_id.eq(user_id))
+            .order(schema::new_white_lists::token_type.asc())
+            .load::<NewWhiteList>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .map(|i| UserWallet {
+                wallet:     wallet.clone(),
+                tokens:     i.allocation,
+                token_type: i.token_type,
+            })
+            .collect();
+    }
+}
+
 #[derive(Debug, Queryable, Serialize, Deserialize, Identifiable)]
 pub struct NewWallet {
-    pub id:      i32,
-    pub user_id: i32,
-    pub link:    String,
+    pub id:       i32,
+    pub user_id:  i32,
+    pub link:     String,
+    pub verified: bool,
 }
 
 
 impl NewWallet {
-    pub fn create(user_id: i32, link: String, token_type: i16) -> () {
-        let _connection = establish_connection();
-        if schema::new_wallets::table
+    /// Adds an unverified wallet for the user. A wallet already linked to
+    /// the same user is returned as is. Unverified claims of other users do
+    /// not get in the way; a wallet verified by someone else is refused.
+    pub fn create(user_id: i32, link: &WalletAddress) -> Result<NewWallet, Error> {
+        NewWallet::create_with(&establish_connection(), user_id, link)
+    }
+    pub fn create_with(_connection: &PgConnection, user_id: i32, link: &WalletAddress) -> Result<NewWallet, Error> {
+        let link = link.to_string();
+        let items = schema::new_wallets::table
             .filter(schema::new_wallets::link.eq(&link))
-            .select(schema::new_wallets::id) 
-            .load::<i32>(&_connection)
-            .is_err() {
-                let form_wallet = NewNewWallet {
-                    user_id: user_id,
-                    link:    link.clone(),
-                };
-        
-                let _new_wallet = diesel::insert_into(schema::new_wallets::table)
-                    .values(&form_wallet)
-                    .get_result::<NewWallet>(&_connection)
-                    .expect("Error saving wallet.");
+            .load::<NewWallet>(_connection)?;
+        if items.iter().any(|i| i.verified && i.user_id != user_id) {
+            return Err(Error::BadRequest("The wallet is linked to another account".to_string()));
+        }
+        if let Some(item) = items.into_iter().find(|i| i.user_id == user_id) {
+            return Ok(item);
         }
-        NewWhiteList::create(user_id, token_type);
+        let form_wallet = NewNewWallet {
+            user_id:  user_id,
+            link:     link,
+            verified: false,
+        };
+        Ok(diesel::insert_into(schema::new_wallets::table)
+            .values(&form_wallet)
+            .get_result::<NewWallet>(_connection)?)
+    }
+    pub fn get_for_user(user_id: i32) -> Vec<NewWallet> {
+        let _connection = establish_connection();
+        return schema::new_wallets::table
+            .filter(schema::new_wallets::user_id.eq(user_id))
+            .order(schema::new_wallets::id.asc())
+            .load::<NewWallet>(&_connection)
+            .expect("E.");
+    }
+    /// The verified wallets of `user_ids`, oldest first. Only these are
+    /// used for on-chain entries: anyone can claim an address, but only
+    /// its owner can verify it.
+    pub fn get_verified_for(user_ids: Vec<i32>) -> Vec<NewWallet> {
+        let _connection = establish_connection();
+        return schema::new_wallets::table
+            .filter(schema::new_wallets::user_id.eq_any(user_ids))
+            .filter(schema::new_wallets::verified.eq(true))
+            .order(schema::new_wallets::id.asc())
+            .load::<NewWallet>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_item(id: i32) -> Result<NewWallet, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::new_wallets::table
+            .filter(schema::new_wallets::id.eq(id))
+            .first::<NewWallet>(&_connection)?);
+    }
+    pub fn get_verified_owner(link: &WalletAddress) -> Option<User> {
+        let _connection = establish_connection();
+        return schema::new_wallets::table
+            .inner_join(schema::users::table.on(schema::users::id.eq(schema::new_wallets::user_id)))
+            .filter(schema::new_wallets::link.eq(link.to_string()))
+            .filter(schema::new_wallets::verified.eq(true))
+            .select(schema::users::all_columns)
+            .first::<User>(&_connection)
+            .ok();
+    }
+    /// `user_id` has proven control over `link`, so the wallet is flagged
+    /// as verified for them and the claims of everyone else are dropped.
+    pub fn mark_verified(user_id: i32, link: &WalletAddress) -> Result<NewWallet, Error> {
+        let _connection = establish_connection();
+        let link = link.to_string();
+        _connection.transaction(|| {
+            diesel::delete (
+                schema::new_wallets::table
+                    .filter(schema::new_wallets::link.eq(&link))
+                    .filter(schema::new_wallets::user_id.ne(user_id))
+            )
+            .execute(&_connection)?;
+            let form_wallet = NewNewWallet {
+                user_id:  user_id,
+                link:     link.clone(),
+                verified: true,
+            };
+            Ok(diesel::insert_into(schema::new_wallets::table)
+                .values(&form_wallet)
+                .on_conflict((schema::new_wallets::user_id, schema::new_wallets::link))
+                .do_update()
+                .set(schema::new_wallets::verified.eq(true))
+                .get_result::<NewWallet>(&_connection)?)
+        })
     }
     pub fn delete(id: i32) -> () {
         let _connection = establish_connection();
@@ -647,8 +765,9 @@ impl NewWallet {
 #[derive(Debug, Deserialize, Insertable)]
 #[table_name="new_wallets"]
 pub struct NewNewWallet {
-    pub user_id: i32,
-    pub link:    String,
+    pub user_id:  i32,
+    pub link:     String,
+    pub verified: bool,
 }
 
 
@@ -675,27 +794,46 @@ pub struct NewWhiteList {
     pub id:         i32,
     pub user_id:    i32,
     pub token_type: i16,
+    pub allocation: String,
 }
 
 impl NewWhiteList { 
-    pub fn create(user_id: i32, token_type: i16) -> () {
+    pub fn create(user_id: i32, token_type: i16, allocation: String) -> Result<(), Error> {
+        NewWhiteList::create_with(&establish_connection(), user_id, token_type, allocation)
+    }
+    pub fn create_with(_connection: &PgConnection, user_id: i32, token_type: i16, allocation: String) -> Result<(), Error> {
+        let form_white_lists = NewNewWhiteList {
+            user_id:    user_id,
+            token_type: token_type,
+            allocation: allocation,
+        }; 
+        diesel::insert_into(schema::new_white_lists::table)
+            .values(&form_white_lists)
+            .on_conflict((schema::new_white_lists::user_id, schema::new_white_lists::token_type))
+            .do_update()
+            .set(schema::new_white_lists::allocation.eq(&form_white_lists.allocation))
+            .execute(_connection)?;
+        Ok(())
+    }
+    /// The white list of a stage without blocked users.
+    pub fn get_for_stage(token_type: i16) -> Vec<NewWhiteList> {
         let _connection = establish_connection();
-        if schema::new_white_lists::table
-            .filter(schema::new_white_lists::user_id.eq(user_id))
+        return schema::new_white_lists::table
+            .inner_join(schema::users::table.on(schema::users::id.eq(schema::new_white_lists::user_id)))
             .filter(schema::new_white_lists::token_type.eq(token_type))
-            .select(schema::new_white_lists::id) 
-            .load::<i32>(&_connection)
-            .is_err() 
-        {
-            let form_white_lists = NewNewWhiteList {
-                user_id:    user_id,
-                token_type: token_type.clone(),
-            }; 
-            let _new_white_lists = diesel::insert_into(schema::new_white_lists::table)
-                .values(&form_white_lists)
-                .get_result::<NewWhiteList>(&_connection)
-                .expect("Error saving white list item.");
-        }
+            .filter(schema::users::perm.ne_all(vec![USERISBLOCK, ADMINISBLOCK]))
+            .order(schema::new_white_lists::id.asc())
+            .select(schema::new_white_lists::all_columns)
+            .load::<NewWhiteList>(&_connection)
+            .expect("E.");
+    }
+    pub fn get_for_user(user_id: i32) -> Vec<NewWhiteList> {
+        let _connection = establish_connection();
+        return schema::new_white_lists::table
+            .filter(schema::new_white_lists::user_id.eq(user_id))
+            .order(schema::new_white_lists::token_type.asc())
+            .load::<NewWhiteList>(&_connection)
+            .expect("E.");
     }
     pub fn delete(user_id: i32, token_type: i16) -> () {
         let _connection = establish_connection();
@@ -723,4 +861,5 @@ impl NewWhiteList {
 pub struct NewNewWhiteList {
     pub user_id:    i32,
     pub token_type: i16,
+    pub allocation: String,
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+use crate::schema;
+use crate::schema::{
+    wallet_challenges,
+    siwe_nonces,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{establish_connection, recover_personal_sign, sign_token, verify_token, SiweMessage, WalletAddress};
+use crate::errors::Error;
+use crate::models::{NewWallet, User};
+
+const CHALLENGE_TTL_MINUTES: i64 = 15;
+const SIWE_NONCE_TTL_MINUTES: i64 = 10;
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct WalletChallenge {
+    pub id:      i32,
+    pub user_id: i32,
+    pub address: String,
+    pub nonce:   String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WalletChallengeJson {
+    pub address: WalletAddress,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WalletChallengeResp {
+    pub message: String,
+    pub nonce:   String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WalletSignatureJson {
+    pub address:   WalletAddress,
+    pub signature: String,
+}
+
+impl WalletChallenge {
+    /// The exact text the wallet is asked to `personal_sign`.
+    pub fn message(&self) -> String {
+        "BJustCoin wallet verification\n\nAddress: ".to_string()
+            + &self.address
+            + "\nNonce: "
+            + &self.nonce
+    }
+
+    pub fn get_resp(&self) -> WalletChallengeResp {
+        WalletChallengeResp {
+            message: self.message(),
+            nonce:   self.nonce.clone(),
+            expires: self.expires,
+        }
+    }
+
+    /// Issues a fresh nonce for the (user, address) pair, replacing any
+    /// earlier unanswered challenge.
+    pub fn create(user_id: i32, address: &WalletAddress) -> Result<WalletChallenge, Error> {
+        use rand::Rng;
+
+        let _connection = establish_connection();
+        let form = NewWalletChallenge {
+            user_id: user_id,
+            address: address.to_string(),
+            nonce:   hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            expires: chrono::Utc::now().naive_utc() + chrono::Duration::minutes(CHALLENGE_TTL_MINUTES),
+        };
+        Ok(diesel::insert_into(schema::wallet_challenges::table)
+            .values(&form)
+            .on_conflict((schema::wallet_challenges::user_id, schema::wallet_challenges::address))
+            .do_update()
+            .set((
+                schema::wallet_challenges::nonce.eq(&form.nonce),
+                schema::wallet_challenges::expires.eq(form.expires),
+            ))
+            .get_result::<WalletChallenge>(&_connection)?)
+    }
+
+    /// Checks the signature against the pending challenge. The challenge is
+    /// consumed either way, in one statement, so a nonce can be answered
+    /// only once.
+    pub fn verify(user_id: i32, address: &WalletAddress, signature: &str) -> Result<NewWallet, Error> {
+        let _connection = establish_connection();
+        let challenge = diesel::delete (
+            schema::wallet_challenges::table
+                .filter(schema::wallet_challenges::user_id.eq(user_id))
+                .filter(schema::wallet_challenges::address.eq(address.to_string()))
+        )
+        .get_result::<WalletChallenge>(&_connection)
+        .map_err(|_| Error::BadRequest("No pending challenge for this wallet".to_string()))?;
+
+        if challenge.expires < chrono::Utc::now().naive_utc() {
+            return Err(Error::BadRequest("Challenge has expired".to_string()));
+        }
+        let signer = recover_personal_sign(&challenge.message(), signature)
+            .map_err(Error::BadRequest)?;
+        if &signer != address {
+            return Err(Error::BadRequest("Signature does not belong to this wallet".to_string()));
+        }
+        NewWallet::mark_verified(user_id, address)
+    }
+}
+
+#[derive(Debug, Deserialize, Insertable)]
+#[table_name="wallet_challenges"]
+pub struct NewWalletChallenge {
+    pub user_id: i32,
+    pub address: String,
+    pub nonce:   String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Insertable)]
+#[table_name="siwe_nonces"]
+pub struct SiweNonce {
+    pub nonce:   String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SiweLoginJson {
+    pub message:   String,
+    pub signature: String,
+}
+
+impl SiweNonce {
+    /// Issues a nonce without storing it: 8 random bytes and the expiry,
+    /// signed with the secret key, all hex. Only spent nonces are stored,
+    /// so fetching nonces costs no rows.
+    pub fn create() -> SiweNonce {
+        use rand::Rng;
+
+        let expires = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(SIWE_NONCE_TTL_MINUTES);
+        let value = hex::encode(rand::thread_rng().gen::<[u8; 8]>())
+            + &format!("{:08x}", expires.timestamp() as u32);
+        SiweNonce {
+            nonce:   value.clone() + &sign_token("siwe", &value),
+            expires: expires,
+        }
+    }
+
+    /// The expiry of a nonce we issued, None for anything else.
+    fn parse(nonce: &str) -> Option<chrono::NaiveDateTime> {
+        if nonce.len() != 88 || !nonce.is_ascii() {
+            return None;
+        }
+        let (value, token) = nonce.split_at(24);
+        if !verify_token("siwe", value, token) {
+            return None;
+        }
+        let expires = u32::from_str_radix(&value[16..], 16).ok()?;
+        chrono::NaiveDateTime::from_timestamp_opt(expires as i64, 0)
+    }
+
+    /// Records the nonce as spent and reports whether it was issued by us,
+    /// is still live and was not spent before.
+    pub fn consume(nonce: &str) -> bool {
+        let now = chrono::Utc::now().naive_utc();
+        let expires = match SiweNonce::parse(nonce) {
+            Some(expires) if expires > now => expires,
+            _ => return false,
+        };
+        let _connection = establish_connection();
+        let _ = diesel::delete(schema::siwe_nonces::table.filter(schema::siwe_nonces::expires.lt(now)))
+            .execute(&_connection);
+        diesel::insert_into(schema::siwe_nonces::table)
+            .values(&SiweNonce {
+                nonce:   nonce.to_string(),
+                expires: expires,
+            })
+            .on_conflict_do_nothing()
+            .execute(&_connection)
+            .map_or(false, |count| count == 1)
+    }
+
+    /// Verifies a signed EIP-4361 message and returns the user owning the
+    /// wallet. When `request_user` is given and the wallet is not linked yet,
+    /// it is linked to them.
+    pub fn login(data: &SiweLoginJson, request_user: Option<User>) -> Result<User, Error> {
+        let message = SiweMessage::parse(&data.message).map_err(Error::BadRequest)?;
+        message.validate (
+            &crate::vars::siwe_domain(),
+            &crate::vars::siwe_uri(),
+            crate::vars::siwe_chain_id(),
+        ).map_err(Error::BadRequest)?;
+        // the nonce is only spent by a valid signature, so a forged request
+        // cannot burn a nonce issued to someone else
+        let signer = recover_personal_sign(&data.message, &data.signature)
+            .map_err(Error::BadRequest)?;
+        if signer != message.address {
+            return Err(Error::BadRequest("Signature does not belong to this wallet".to_string()));
+        }
+        if !SiweNonce::consume(&message.nonce) {
+            return Err(Error::BadRequest("SIWE nonce is unknown or expired".to_string()));
+        }
+
+        if let Some(_user) = NewWallet::get_verified_owner(&message.address) {
+            return Ok(_user);
+        }
+        match request_user {
+            Some(_user) => {
+                NewWallet::mark_verified(_user.id, &message.address)?;
+                Ok(_user)
+            },
+            None => Err(Error::NotFound("No account is linked to this wallet".to_string())),
+        }
+    }
+}
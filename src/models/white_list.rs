@@ -0,0 +1,369 @@
+use crate::schema;
+use crate::schema::{
+    white_list_trees,
+    white_list_mismatches,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::{
+    establish_connection,
+    parse_units,
+    white_list_leaf,
+    MerkleTree,
+    WalletAddress,
+};
+use crate::errors::Error;
+use crate::models::{NewWallet, NewWhiteList, SuggestItem};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WhiteListEntry {
+    pub user_id:    i32,
+    pub address:    WalletAddress,
+    pub stage:      i16,
+    pub allocation: String,
+}
+
+impl WhiteListEntry {
+    pub fn leaf(&self) -> Result<[u8; 32], String> {
+        let units = parse_units(&self.allocation, crate::vars::token_decimals())
+            .ok_or_else(|| "allocation is not a valid amount: ".to_string() + &self.allocation)?;
+        let stage = u8::try_from(self.stage)
+            .map_err(|_| "stage does not fit the uint8 leaf field: ".to_string() + &self.stage.to_string())?;
+        Ok(white_list_leaf(&self.address, stage, units))
+    }
+}
+
+#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable)]
+pub struct WhiteListTree {
+    pub id:         i32,
+    pub token_type: i16,
+    pub root:       String,
+    pub entries:    serde_json::Value,
+    pub created:    chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WhiteListRootResp {
+    pub token_type: i16,
+    pub root:       String,
+    pub count:      usize,
+    pub created:    chrono::NaiveDateTime,
+    /// users on the white list who were left out because they have no
+    /// verified wallet or an unusable allocation
+    pub skipped:    Vec<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WhiteListProofResp {
+    pub address:    WalletAddress,
+    pub token_type: i16,
+    pub allocation: String,
+    pub leaf:       String,
+    pub proof:      Vec<String>,
+    pub root:       String,
+}
+
+fn to_hex(hash: &[u8; 32]) -> String {
+    "0x".to_string() + &hex::encode(hash)
+}
+
+impl WhiteListTree {
+    pub fn get_entries(&self) -> Vec<WhiteListEntry> {
+        serde_json::from_value(self.entries.clone()).unwrap_or_default()
+    }
+
+    /// One entry per white-listed user, on their oldest verified wallet.
+    /// Users without a verified wallet or with an unusable allocation are
+    /// returned as skipped.
+    fn select_entries(token_type: i16, items: &[NewWhiteList], wallets: &[NewWallet]) -> (Vec<WhiteListEntry>, Vec<i32>) {
+        let mut entries = Vec::new();
+        let mut skipped = Vec::new();
+        let mut addresses = HashSet::new();
+        for item in items.iter() {
+            let wallet = wallets.iter()
+                .find(|w| w.user_id == item.user_id && w.verified)
+                .and_then(|w| WalletAddress::parse(&w.link).ok())
+                .filter(|address| addresses.insert(address.clone()));
+            let entry = match wallet {
+                Some(address) => WhiteListEntry {
+                    user_id:    item.user_id,
+                    address:    address,
+                    stage:      token_type,
+                    allocation: item.allocation.clone(),
+                },
+                None => {
+                    skipped.push(item.user_id);
+                    continue;
+                },
+            };
+            if entry.leaf().is_err() {
+                skipped.push(item.user_id);
+                continue;
+            }
+            entries.push(entry);
+        }
+        (entries, skipped)
+    }
+
+    fn build(entries: &Vec<WhiteListEntry>) -> MerkleTree {
+        MerkleTree::new(entries.iter().filter_map(|i| i.leaf().ok()).collect())
+    }
+
+    /// Snapshots the current white list of a stage and stores its root.
+    /// Proofs are always served from the latest snapshot, so they keep
+    /// matching the root on chain until the next publish.
+    pub fn publish(token_type: i16) -> Result<WhiteListRootResp, Error> {
+        if u8::try_from(token_type).is_err() {
+            return Err(Error::BadRequest("Invalid stage: ".to_string() + &token_type.to_string()));
+        }
+        let _connection = establish_connection();
+        let items = NewWhiteList::get_for_stage(token_type);
+        let wallets = NewWallet::get_verified_for(items.iter().map(|i| i.user_id).collect());
+        let (entries, skipped) = WhiteListTree::select_entries(token_type, &items, &wallets);
+        let root = to_hex(&WhiteListTree::build(&entries).root());
+        let form = NewWhiteListTree {
+            token_type: token_type,
+            root:       root,
+            entries:    serde_json::to_value(&entries).expect("E."),
+            created:    chrono::Utc::now().naive_utc(),
+        };
+        let tree = diesel::insert_into(schema::white_list_trees::table)
+            .values(&form)
+            .get_result::<WhiteListTree>(&_connection)?;
+        Ok(WhiteListRootResp {
+            token_type: tree.token_type,
+            root:       tree.root,
+            count:      entries.len(),
+            created:    tree.created,
+            skipped:    skipped,
+        })
+    }
+
+    pub fn get_latest(token_type: i16) -> Result<WhiteListTree, Error> {
+        let _connection = establish_connection();
+        return Ok(schema::white_list_trees::table
+            .filter(schema::white_list_trees::token_type.eq(token_type))
+            .order(schema::white_list_trees::id.desc())
+            .first::<WhiteListTree>(&_connection)?);
+    }
+
+    pub fn get_root(&self) -> WhiteListRootResp {
+        WhiteListRootResp {
+            token_type: self.token_type,
+            root:       self.root.clone(),
+            count:      self.get_entries().len(),
+            created:    self.created,
+            skipped:    Vec::new(),
+        }
+    }
+
+    pub fn get_proof(&self, address: &WalletAddress) -> Result<WhiteListProofResp, Error> {
+        let entries = self.get_entries();
+        let entry = entries.iter()
+            .find(|i| &i.address == address)
+            .ok_or_else(|| Error::NotFound("The wallet is not on the white list".to_string()))?;
+        let leaf = entry.leaf().map_err(Error::BadRequest)?;
+        let proof = WhiteListTree::build(&entries)
+            .proof(&leaf)
+            .ok_or_else(|| Error::NotFound("The wallet is not on the white list".to_string()))?;
+        Ok(WhiteListProofResp {
+            address:    entry.address.clone(),
+            token_type: self.token_type,
+            allocation: entry.allocation.clone(),
+            leaf:       to_hex(&leaf),
+            proof:      proof.iter().map(to_hex).collect(),
+            root:       self.root.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="white_list_trees"]
+pub struct NewWhiteListTree {
+    pub token_type: i16,
+    pub root:       String,
+    pub entries:    serde_json::Value,
+    pub created:    chrono::NaiveDateTime,
+}
+
+
+/*
+kind
+
+approved_without_white_list - the application is approved, the user is not on the stage white list
+approved_without_wallet     - the application is approved, its address is not among the user's wallets
+white_list_without_approval - the user is on a stage white list without an approved application
+*/
+#[derive(Debug, Queryable, Deserialize, Serialize, Insertable)]
+#[table_name="white_list_mismatches"]
+pub struct WhiteListMismatch {
+    pub kind:       String,
+    pub user_id:    Option<i32>,
+    pub email:      String,
+    pub token_type: i16,
+    pub address:    Option<String>,
+    pub checked:    chrono::NaiveDateTime,
+}
+
+impl WhiteListMismatch {
+    /// Compares approved `suggest_items` against `new_white_lists` and
+    /// `new_wallets`.
+    pub fn find_all() -> Vec<WhiteListMismatch> {
+        let _connection = establish_connection();
+        let checked = chrono::Utc::now().naive_utc();
+        let approved = schema::suggest_items::table
+            .filter(schema::suggest_items::status.eq(1))
+            .load::<SuggestItem>(&_connection)
+            .expect("E.");
+        let users: HashMap<String, i32> = schema::users::table
+            .select((schema::users::email, schema::users::id))
+            .load::<(String, i32)>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .collect();
+        let white_lists = schema::new_white_lists::table
+            .load::<NewWhiteList>(&_connection)
+            .expect("E.");
+        let wallets: HashSet<(i32, String)> = schema::new_wallets::table
+            .load::<NewWallet>(&_connection)
+            .expect("E.")
+            .into_iter()
+            .map(|w| (w.user_id, w.link))
+            .collect();
+
+        let listed: HashSet<(i32, i16)> = white_lists.iter()
+            .map(|i| (i.user_id, i.token_type))
+            .collect();
+        let mut approved_stages = HashSet::new();
+        let mut stack = Vec::new();
+        for item in approved.iter() {
+            let user_id = users.get(&item.email).cloned();
+            let address = WalletAddress::parse(&item.address)
+                .map(|a| a.to_string())
+                .unwrap_or_else(|_| item.address.clone());
+            if let Some(user_id) = user_id {
+                approved_stages.insert((user_id, item.token_type));
+            }
+            if user_id.map_or(true, |id| !listed.contains(&(id, item.token_type))) {
+                stack.push(WhiteListMismatch {
+                    kind:       "approved_without_white_list".to_string(),
+                    user_id:    user_id,
+                    email:      item.email.clone(),
+                    token_type: item.token_type,
+                    address:    Some(address.clone()),
+                    checked:    checked,
+                });
+            }
+            if user_id.map_or(true, |id| !wallets.contains(&(id, address.clone()))) {
+                stack.push(WhiteListMismatch {
+                    kind:       "approved_without_wallet".to_string(),
+                    user_id:    user_id,
+                    email:      item.email.clone(),
+                    token_type: item.token_type,
+                    address:    Some(address),
+                    checked:    checked,
+                });
+            }
+        }
+        for item in white_lists.iter() {
+            if !approved_stages.contains(&(item.user_id, item.token_type)) {
+                let email = users.iter()
+                    .find(|(_, id)| **id == item.user_id)
+                    .map(|(email, _)| email.clone())
+                    .unwrap_or_default();
+                stack.push(WhiteListMismatch {
+                    kind:       "white_list_without_approval".to_string(),
+                    user_id:    Some(item.user_id),
+                    email:      email,
+                    token_type: item.token_type,
+                    address:    None,
+                    checked:    checked,
+                });
+            }
+        }
+        return stack;
+    }
+    /// Replaces the stored result of the last reconciliation with `list`.
+    pub fn store(list: &Vec<WhiteListMismatch>) -> Result<(), Error> {
+        let _connection = establish_connection();
+        _connection.transaction(|| {
+            diesel::delete(schema::white_list_mismatches::table).execute(&_connection)?;
+            diesel::insert_into(schema::white_list_mismatches::table)
+                .values(list)
+                .execute(&_connection)?;
+            Ok(())
+        })
+    }
+
+    /// The mismatches found by the last run of the reconcile job.
+    pub fn get_stored() -> Vec<WhiteListMismatch> {
+        let _connection = establish_connection();
+        return schema::white_list_mismatches::table
+            .order(schema::white_list_mismatches::id.asc())
+            .select((
+                schema::white_list_mismatches::kind,
+                schema::white_list_mismatches::user_id,
+                schema::white_list_mismatches::email,
+                schema::white_list_mismatches::token_type,
+                schema::white_list_mismatches::address,
+                schema::white_list_mismatches::checked,
+            ))
+            .load::<WhiteListMismatch>(&_connection)
+            .expect("E.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    fn listed(user_id: i32) -> NewWhiteList {
+        NewWhiteList {
+            id:         user_id,
+            user_id:    user_id,
+            token_type: 1,
+            allocation: "100".to_string(),
+        }
+    }
+
+    fn wallet(id: i32, user_id: i32, verified: bool) -> NewWallet {
+        NewWallet {
+            id:       id,
+            user_id:  user_id,
+            link:     ADDRESS.to_string(),
+            verified: verified,
+        }
+    }
+
+    #[test]
+    fn verified_owner_beats_an_earlier_claimant() {
+        let items = vec![listed(1), listed(2)];
+        // user 1 claimed the address first, user 2 proved they own it
+        let wallets = vec![wallet(1, 1, false), wallet(2, 2, true)];
+        let (entries, skipped) = WhiteListTree::select_entries(1, &items, &wallets);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].user_id, 2);
+        assert_eq!(entries[0].address.to_string(), ADDRESS);
+        assert_eq!(skipped, vec![1]);
+    }
+
+    #[test]
+    fn unverified_claims_get_no_leaf() {
+        let items = vec![listed(1), listed(2)];
+        let wallets = vec![wallet(1, 1, false), wallet(2, 2, false)];
+        let (entries, skipped) = WhiteListTree::select_entries(1, &items, &wallets);
+        assert!(entries.is_empty());
+        assert_eq!(skipped, vec![1, 2]);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::schema;
+use crate::schema::ws_tickets;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use crate::utils::establish_connection;
+use crate::errors::Error;
+
+const WS_TICKET_TTL_SECONDS: i64 = 30;
+
+/// A short-lived, single-use credential for the websocket handshake, so
+/// the `secret` never has to travel in a URL where proxies log it.
+#[derive(Debug, Queryable, Deserialize, Serialize, Insertable)]
+#[table_name="ws_tickets"]
+pub struct WsTicket {
+    pub ticket:  String,
+    pub user_id: i32,
+    pub expires: chrono::NaiveDateTime,
+}
+
+impl WsTicket {
+    pub fn create(user_id: i32) -> Result<WsTicket, Error> {
+        use rand::Rng;
+
+        let _connection = establish_connection();
+        let now = chrono::Utc::now().naive_utc();
+        diesel::delete(schema::ws_tickets::table.filter(schema::ws_tickets::expires.lt(now)))
+            .execute(&_connection)?;
+        let form = WsTicket {
+            ticket:  hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            user_id: user_id,
+            expires: now + chrono::Duration::seconds(WS_TICKET_TTL_SECONDS),
+        };
+        Ok(diesel::insert_into(schema::ws_tickets::table)
+            .values(&form)
+            .get_result::<WsTicket>(&_connection)?)
+    }
+
+    /// Deletes the ticket and returns its user if it was still live.
+    pub fn consume(ticket: &str) -> Option<i32> {
+        let _connection = establish_connection();
+        diesel::delete (
+            schema::ws_tickets::table
+                .filter(schema::ws_tickets::ticket.eq(ticket))
+                .filter(schema::ws_tickets::expires.gt(chrono::Utc::now().naive_utc()))
+        )
+        .returning(schema::ws_tickets::user_id)
+        .get_result::<i32>(&_connection)
+        .ok()
+    }
+}
@@ -2,11 +2,24 @@ use actix_web::web;
 use crate::views::{
     auth,
     admin_progs,
+    payments,
+    export,
+    wallets,
+    mail,
+    campaigns,
+    notifications,
 };
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg
     .configure(auth::auth_routes)
     .configure(admin_progs::admin_routes)
+    .configure(payments::payment_routes)
+    .configure(export::export_routes)
+    .configure(wallets::wallet_routes)
+    .configure(mail::mail_routes)
+    .configure(campaigns::campaign_routes)
+    .configure(notifications::notification_routes)
+    .configure(crate::websocket::websocket_routes)
     ;
 }
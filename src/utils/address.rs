@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use crate::utils::keccak256;
+
+/// An Ethereum address, always held in its EIP-55 checksummed form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct WalletAddress(String);
+
+impl WalletAddress {
+    /// Accepts `0x` + 40 hex digits. All-lowercase and all-uppercase input is
+    /// taken as is; mixed case must carry a valid EIP-55 checksum.
+    pub fn parse(value: &str) -> Result<WalletAddress, String> {
+        let value = value.trim();
+        let digits = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(digits) => digits,
+            None => return Err("Wallet address must start with 0x".to_string()),
+        };
+        if digits.len() != 40 {
+            return Err("Wallet address must have 40 hex digits, got ".to_string() + &digits.len().to_string());
+        }
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("Wallet address contains non-hex characters".to_string());
+        }
+        let checksummed = to_checksum(digits);
+        let is_lower = digits.chars().all(|c| !c.is_ascii_uppercase());
+        let is_upper = digits.chars().all(|c| !c.is_ascii_lowercase());
+        if !is_lower && !is_upper && digits != &checksummed[2..] {
+            return Err("Wallet address has an invalid EIP-55 checksum".to_string());
+        }
+        Ok(WalletAddress(checksummed))
+    }
+
+    /// Raw 20 bytes of the address.
+    pub fn to_bytes(&self) -> [u8; 20] {
+        let mut out = [0u8; 20];
+        out.copy_from_slice(&hex::decode(&self.0[2..]).expect("E."));
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; 20]) -> WalletAddress {
+        WalletAddress(to_checksum(&hex::encode(bytes)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// EIP-55: uppercase every hex letter whose nibble in keccak256(lowercase
+/// address) is 8 or higher.
+fn to_checksum(digits: &str) -> String {
+    let lower = digits.to_ascii_lowercase();
+    let hash = keccak256(lower.as_bytes());
+    let mut out = String::with_capacity(42);
+    out.push_str("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+        if c.is_ascii_alphabetic() && nibble >= 8 {
+            out.push(c.to_ascii_uppercase());
+        }
+        else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl TryFrom<String> for WalletAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        WalletAddress::parse(&value)
+    }
+}
+
+impl From<WalletAddress> for String {
+    fn from(address: WalletAddress) -> String {
+        address.0
+    }
+}
+
+impl fmt::Display for WalletAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the test vectors from the EIP-55 specification
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn checksums_lowercase_input() {
+        for address in CHECKSUMMED.iter() {
+            let parsed = WalletAddress::parse(&address.to_ascii_lowercase()).unwrap();
+            assert_eq!(parsed.as_str(), *address);
+        }
+    }
+
+    #[test]
+    fn accepts_valid_checksum() {
+        for address in CHECKSUMMED.iter() {
+            assert_eq!(WalletAddress::parse(address).unwrap().as_str(), *address);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_checksum() {
+        assert!(WalletAddress::parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(WalletAddress::parse("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+        assert!(WalletAddress::parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").is_err());
+        assert!(WalletAddress::parse("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg").is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let address = WalletAddress::parse(CHECKSUMMED[0]).unwrap();
+        assert_eq!(WalletAddress::from_bytes(&address.to_bytes()), address);
+    }
+}
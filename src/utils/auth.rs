@@ -20,7 +20,7 @@ pub fn verify(hash: &str, password: &str) -> Result<bool, AuthError> {
       .with_password(password)
       .with_secret_key(vars::secret_key().as_str())
       .verify()
-      .map_err(|_| AuthError::AuthenticationError(String::from("Не удалось подтвердить пароль")))
+      .map_err(|_| AuthError::AuthenticationError(String::from("Could not verify the password")))
 }
 
 pub fn is_json_request(req: &HttpRequest) -> bool {
@@ -0,0 +1,261 @@
+use actix_web::web::{self, Bytes};
+use futures::stream::{self, Stream, StreamExt};
+use std::io::{self, Read};
+use crate::models::{Holder, SuggestItem, Subscriber, User};
+
+/// Rows are pulled from the database in pages of this size, ordered by id,
+/// so an export never holds a whole table in memory.
+const EXPORT_PAGE: i64 = 500;
+/// XLSX files are sent in chunks of this size.
+const FILE_CHUNK: usize = 64 * 1024;
+
+/// Spreadsheets run cells starting with these as formulas.
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// Prefixes text that a spreadsheet would run as a formula with `'`.
+/// Plain numbers such as "-5" are left alone.
+pub fn escape_cell(value: &str) -> String {
+    if value.starts_with(FORMULA_PREFIXES) && value.parse::<f64>().is_err() {
+        return "'".to_string() + value;
+    }
+    value.to_string()
+}
+
+pub trait ExportRow {
+    fn headers() -> Vec<&'static str>;
+    fn id(&self) -> i32;
+    fn columns(&self) -> Vec<String>;
+}
+
+impl ExportRow for Holder {
+    fn headers() -> Vec<&'static str> {
+        vec!["id", "address", "count", "stage", "count2"]
+    }
+    fn id(&self) -> i32 {
+        self.id
+    }
+    fn columns(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.address.clone(),
+            self.count.to_string(),
+            self.stage.clone(),
+            self.count2.clone(),
+        ]
+    }
+}
+
+impl ExportRow for Subscriber {
+    fn headers() -> Vec<&'static str> {
+        vec!["id", "email", "ip", "created", "confirmed"]
+    }
+    fn id(&self) -> i32 {
+        self.id
+    }
+    fn columns(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.email.clone(),
+            self.ip.clone().unwrap_or_default(),
+            self.created.to_string(),
+            self.confirmed.map(|c| c.to_string()).unwrap_or_default(),
+        ]
+    }
+}
+
+impl ExportRow for SuggestItem {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "id", "first_name", "middle_name", "last_name", "email", "phone", "mobile",
+            "is_agree", "address", "created", "tokens", "token_type", "status",
+        ]
+    }
+    fn id(&self) -> i32 {
+        self.id
+    }
+    fn columns(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.first_name.clone(),
+            self.middle_name.clone(),
+            self.last_name.clone(),
+            self.email.clone(),
+            self.phone.clone(),
+            self.mobile.clone(),
+            self.is_agree.to_string(),
+            self.address.clone(),
+            self.created.to_string(),
+            self.tokens.clone(),
+            User::get_tokenomic_type(self.token_type),
+            self.status.to_string(),
+        ]
+    }
+}
+
+impl ExportRow for User {
+    fn headers() -> Vec<&'static str> {
+        vec!["id", "first_name", "last_name", "email", "phone", "perm", "created"]
+    }
+    fn id(&self) -> i32 {
+        self.id
+    }
+    fn columns(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.first_name.clone(),
+            self.last_name.clone(),
+            self.email.clone(),
+            self.phone.clone().unwrap_or_default(),
+            self.perm.to_string(),
+            self.created.to_string(),
+        ]
+    }
+}
+
+fn csv_chunk(records: Vec<Vec<String>>) -> Result<Bytes, io::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for record in records.iter() {
+        writer.write_record(record.iter().map(|i| escape_cell(i)))?;
+    }
+    writer.into_inner()
+        .map(Bytes::from)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+}
+
+/// Streams `T` rows as CSV, fetching the next page only when the client
+/// has consumed the previous one.
+pub fn csv_stream<T, F>(fetch: F) -> impl Stream<Item = Result<Bytes, io::Error>>
+where
+    T: ExportRow + Send + 'static,
+    F: Fn(i32, i64) -> Vec<T> + Clone + Send + 'static,
+{
+    let header = stream::once(async {
+        csv_chunk(vec![T::headers().iter().map(|h| h.to_string()).collect()])
+    });
+    let rows = stream::unfold(Some(0), move |after_id| {
+        let fetch = fetch.clone();
+        async move {
+            let after_id = after_id?;
+            let page = match web::block(move || fetch(after_id, EXPORT_PAGE)).await {
+                Ok(page) => page,
+                Err(err) => return Some((Err(io::Error::new(io::ErrorKind::Other, err.to_string())), None)),
+            };
+            if page.is_empty() {
+                return None;
+            }
+            let next = match (page.len() as i64) < EXPORT_PAGE {
+                true => None,
+                false => page.last().map(|i| i.id()),
+            };
+            let chunk = csv_chunk(page.iter().map(|i| i.columns()).collect());
+            Some((chunk, next))
+        }
+    });
+    header.chain(rows)
+}
+
+/// Cells written as strings are never evaluated as formulas, so unlike
+/// CSV they are stored as they are.
+fn xlsx_row(worksheet: &mut rust_xlsxwriter::Worksheet, row: u32, values: &[String]) -> Result<(), rust_xlsxwriter::XlsxError> {
+    for (col, value) in values.iter().enumerate() {
+        worksheet.write_string(row, col as u16, value)?;
+    }
+    Ok(())
+}
+
+/// Builds an XLSX workbook page by page into a temporary file and streams
+/// it. The worksheet is written in constant-memory mode and the file is
+/// sent in chunks, so neither the rows nor the file are held in memory.
+pub async fn xlsx_stream<T, F>(sheet: &'static str, fetch: F) -> Result<impl Stream<Item = Result<Bytes, io::Error>>, io::Error>
+where
+    T: ExportRow + Send + 'static,
+    F: Fn(i32, i64) -> Vec<T> + Send + 'static,
+{
+    let to_io = |err: rust_xlsxwriter::XlsxError| io::Error::new(io::ErrorKind::Other, err.to_string());
+    let file = web::block(move || {
+        let path = std::env::temp_dir().join(format!("export-{}.xlsx", uuid::Uuid::new_v4()));
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let worksheet = workbook.add_worksheet_with_constant_memory();
+        worksheet.set_name(sheet).map_err(to_io)?;
+        let headers: Vec<String> = T::headers().iter().map(|h| h.to_string()).collect();
+        xlsx_row(worksheet, 0, &headers).map_err(to_io)?;
+        let mut row: u32 = 1;
+        let mut after_id = 0;
+        loop {
+            let page = fetch(after_id, EXPORT_PAGE);
+            for item in page.iter() {
+                xlsx_row(worksheet, row, &item.columns()).map_err(to_io)?;
+                row += 1;
+            }
+            match page.last() {
+                Some(last) if (page.len() as i64) == EXPORT_PAGE => after_id = last.id(),
+                _ => break,
+            }
+        }
+        let saved = workbook.save(&path).map_err(to_io);
+        // the open handle keeps the data readable after the name is gone
+        let file = saved.and_then(|_| std::fs::File::open(&path));
+        let _ = std::fs::remove_file(&path);
+        file
+    })
+    .await
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))??;
+
+    Ok(stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let read = web::block(move || {
+            let mut chunk = vec![0; FILE_CHUNK];
+            let n = file.read(&mut chunk)?;
+            chunk.truncate(n);
+            Ok::<_, io::Error>((chunk, file))
+        }).await;
+        match read {
+            Ok(Ok((chunk, _))) if chunk.is_empty() => None,
+            Ok(Ok((chunk, file))) => Some((Ok(Bytes::from(chunk)), Some(file))),
+            Ok(Err(err)) => Some((Err(err), None)),
+            Err(err) => Some((Err(io::Error::new(io::ErrorKind::Other, err.to_string())), None)),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calamine::{open_workbook, Data, Reader, Xlsx};
+
+    #[test]
+    fn xlsx_cells_round_trip_unchanged() {
+        let values: Vec<String> = ["+1 555 0100", "-", "=SUM(A1:A2)", "@user", "plain"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let path = std::env::temp_dir().join(format!("export-test-{}.xlsx", uuid::Uuid::new_v4()));
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let worksheet = workbook.add_worksheet_with_constant_memory();
+        xlsx_row(worksheet, 0, &values).unwrap();
+        workbook.save(&path).unwrap();
+
+        let mut workbook: Xlsx<_> = open_workbook(&path).unwrap();
+        let range = workbook.worksheet_range_at(0).unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+        let cells: Vec<String> = range.rows()
+            .next()
+            .unwrap()
+            .iter()
+            .map(|cell| match cell {
+                Data::String(s) => s.clone(),
+                other => panic!("not a string cell: {:?}", other),
+            })
+            .collect();
+        assert_eq!(cells, values);
+    }
+
+    #[test]
+    fn csv_cells_are_escaped() {
+        assert_eq!(escape_cell("=SUM(A1:A2)"), "'=SUM(A1:A2)");
+        assert_eq!(escape_cell("-5"), "-5");
+        assert_eq!(escape_cell("plain"), "plain");
+    }
+}
@@ -0,0 +1,160 @@
+use crate::utils::{keccak256, WalletAddress};
+
+/// A keccak-256 Merkle tree with sorted leaves and sorted-pair hashing, the
+/// layout OpenZeppelin's `MerkleProof.verify` expects.
+pub struct MerkleTree {
+    layers: Vec<Vec<[u8; 32]>>,
+}
+
+fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 64];
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    data[..32].copy_from_slice(first);
+    data[32..].copy_from_slice(second);
+    keccak256(&data)
+}
+
+impl MerkleTree {
+    pub fn new(mut leaves: Vec<[u8; 32]>) -> MerkleTree {
+        leaves.sort();
+        leaves.dedup();
+        let mut layers = vec![leaves];
+        while layers.last().map_or(false, |l| l.len() > 1) {
+            let next = layers.last().unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    // an odd node is carried up unchanged
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(next);
+        }
+        MerkleTree { layers: layers }
+    }
+
+    /// Zero hash for an empty tree.
+    pub fn root(&self) -> [u8; 32] {
+        self.layers.last()
+            .and_then(|l| l.first())
+            .cloned()
+            .unwrap_or([0u8; 32])
+    }
+
+    pub fn proof(&self, leaf: &[u8; 32]) -> Option<Vec<[u8; 32]>> {
+        let mut index = self.layers[0].binary_search(leaf).ok()?;
+        let mut proof = Vec::new();
+        for layer in self.layers.iter().take(self.layers.len() - 1) {
+            let sibling = index ^ 1;
+            if sibling < layer.len() {
+                proof.push(layer[sibling]);
+            }
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+/// `keccak256(abi.encodePacked(address, uint8 stage, uint256 allocation))`
+pub fn white_list_leaf(address: &WalletAddress, stage: u8, allocation: u128) -> [u8; 32] {
+    let mut data = Vec::with_capacity(53);
+    data.extend_from_slice(&address.to_bytes());
+    data.push(stage);
+    data.extend_from_slice(&[0u8; 16]);
+    data.extend_from_slice(&allocation.to_be_bytes());
+    keccak256(&data)
+}
+
+/// Converts a decimal amount such as "1500.25" into integer base units.
+pub fn parse_units(value: &str, decimals: u32) -> Option<u128> {
+    let value = value.trim();
+    let (whole, fraction) = match value.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (value, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if fraction.len() > decimals as usize
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let whole = match whole.is_empty() {
+        true => 0,
+        false => whole.parse::<u128>().ok()?,
+    };
+    let mut units = whole.checked_mul(10u128.checked_pow(decimals)?)?;
+    if !fraction.is_empty() {
+        let scale = 10u128.pow(decimals - fraction.len() as u32);
+        units = units.checked_add(fraction.parse::<u128>().ok()? * scale)?;
+    }
+    Some(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves() -> Vec<[u8; 32]> {
+        (1u8..=3).map(|i| keccak256(&[i])).collect()
+    }
+
+    /// What `MerkleProof.verify` does on chain.
+    fn verify(proof: &[[u8; 32]], root: &[u8; 32], leaf: &[u8; 32]) -> bool {
+        let computed = proof.iter().fold(*leaf, |hash, sibling| hash_pair(&hash, sibling));
+        &computed == root
+    }
+
+    #[test]
+    fn root_of_fixed_set() {
+        let mut sorted = leaves();
+        sorted.sort();
+        let expected = hash_pair(&hash_pair(&sorted[0], &sorted[1]), &sorted[2]);
+
+        let tree = MerkleTree::new(leaves());
+        assert_eq!(tree.root(), expected);
+        assert_eq!(
+            hex::encode(tree.root()),
+            "eabc7452eee4f65d21a7edc9987de7047c7f2554db3dfeab6bff2d80e61c2022",
+        );
+    }
+
+    #[test]
+    fn root_does_not_depend_on_leaf_order() {
+        let mut reversed = leaves();
+        reversed.reverse();
+        assert_eq!(MerkleTree::new(reversed).root(), MerkleTree::new(leaves()).root());
+    }
+
+    #[test]
+    fn proofs_verify() {
+        let tree = MerkleTree::new(leaves());
+        for leaf in leaves().iter() {
+            let proof = tree.proof(leaf).unwrap();
+            assert!(verify(&proof, &tree.root(), leaf));
+        }
+    }
+
+    #[test]
+    fn no_proof_for_unknown_leaf() {
+        let tree = MerkleTree::new(leaves());
+        assert!(tree.proof(&keccak256(&[4])).is_none());
+    }
+
+    #[test]
+    fn empty_tree() {
+        assert_eq!(MerkleTree::new(Vec::new()).root(), [0u8; 32]);
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse_units("1500.25", 18), Some(1_500_250_000_000_000_000_000));
+        assert_eq!(parse_units("1", 0), Some(1));
+        assert_eq!(parse_units(".5", 1), Some(5));
+        assert_eq!(parse_units("0.123", 2), None);
+        assert_eq!(parse_units("-1", 18), None);
+        assert_eq!(parse_units("", 18), None);
+    }
+}
@@ -1,8 +1,27 @@
 mod auth;
 mod reqwest;
+mod rpc;
+mod export;
+mod keccak;
+mod address;
+mod signature;
+mod siwe;
+mod merkle;
+mod token;
+mod sendgrid;
 
 pub use self::{
     auth::*,
+    reqwest::*,
+    rpc::*,
+    export::*,
+    keccak::*,
+    address::*,
+    signature::*,
+    siwe::*,
+    merkle::*,
+    token::*,
+    sendgrid::*,
 };
 use actix_web::{
     HttpRequest,
@@ -53,6 +72,31 @@ pub fn get_current_user(req: &HttpRequest) -> User {
         .expect("Error.");
 }
 
+/// The signed-in admin, or the error to answer the request with.
+pub fn check_admin(req: &HttpRequest) -> Result<User, crate::errors::Error> {
+    if !is_signed_in(req) {
+        return Err(crate::errors::Error::Unauthorized);
+    }
+    let _request_user = get_current_user(req);
+    if !_request_user.is_admin() {
+        return Err(crate::errors::Error::Forbidden);
+    }
+    Ok(_request_user)
+}
+
+/// The address the request came from. Forwarded headers are only
+/// believed when the connection comes from `TRUSTED_PROXY`, as anyone
+/// else can set them.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr().map(|i| i.ip().to_string());
+    match (crate::vars::trusted_proxy(), peer.as_ref()) {
+        (Some(proxy), Some(peer)) if &proxy == peer => {
+            req.connection_info().realip_remote_addr().map(|i| i.to_string())
+        },
+        _ => peer,
+    }
+}
+
 pub fn get_limit (
     limit: Option<i64>,
     default_limit: i64
@@ -0,0 +1,172 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::time::Duration;
+use crate::utils::WalletAddress;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code:    i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error:  Option<RpcError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcRequest {
+    jsonrpc: &'static str,
+    id:      u32,
+    method:  &'static str,
+    params:  Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    pub address:          String,
+    pub topics:           Vec<String>,
+    pub data:             String,
+    pub block_number:     String,
+    pub block_hash:       String,
+    pub transaction_hash: String,
+    pub log_index:        String,
+    #[serde(default)]
+    pub removed:          bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcBlock {
+    pub number: String,
+    pub hash:   String,
+}
+
+/// A minimal Ethereum JSON-RPC client. It has its own `reqwest::Client`
+/// and logs nothing, since requests carry the node's API key.
+pub struct RpcClient {
+    http: reqwest::Client,
+    url:  String,
+    key:  String,
+}
+
+impl RpcClient {
+    pub fn new(url: String, key: String) -> RpcClient {
+        RpcClient {
+            http: reqwest::Client::builder()
+                .timeout(RPC_TIMEOUT)
+                .build()
+                .expect("E."),
+            url:  url,
+            key:  key,
+        }
+    }
+
+    async fn call<T>(&self, method: &'static str, params: Value) -> Result<T, String>
+    where
+        T: DeserializeOwned + Debug + Send + 'static,
+    {
+        let body = RpcRequest {
+            jsonrpc: "2.0",
+            id:      1,
+            method:  method,
+            params:  params,
+        };
+        let mut req = self.http.post(&self.url).json(&body);
+        if !self.key.is_empty() {
+            req = req.bearer_auth(&self.key);
+        }
+        // without_url: the url may hold the key too
+        let resp = req.send()
+            .await
+            .map_err(|e| method.to_string() + " failed: " + &e.without_url().to_string())?;
+        if !resp.status().is_success() {
+            return Err(method.to_string() + " failed with status " + &resp.status().as_u16().to_string());
+        }
+        let resp = resp.json::<RpcResponse<T>>()
+            .await
+            .map_err(|e| method.to_string() + " returned an invalid body: " + &e.without_url().to_string())?;
+        if let Some(err) = resp.error {
+            return Err(method.to_string() + " error " + &err.code.to_string() + ": " + &err.message);
+        }
+        resp.result.ok_or_else(|| method.to_string() + " returned no result")
+    }
+
+    pub async fn block_number(&self) -> Result<u64, String> {
+        let number = self.call::<String>("eth_blockNumber", json!([])).await?;
+        parse_quantity(&number)
+    }
+
+    pub async fn get_block(&self, number: u64) -> Result<Option<RpcBlock>, String> {
+        self.call::<Option<RpcBlock>>("eth_getBlockByNumber", json!([to_quantity(number), false])).await
+    }
+
+    /// `topics` is the filter as the node takes it, e.g. `[topic0, null, topic2]`.
+    pub async fn get_logs(&self, address: &str, topics: &Value, from: u64, to: u64) -> Result<Vec<RpcLog>, String> {
+        self.call::<Vec<RpcLog>>("eth_getLogs", json!([{
+            "address":   address,
+            "topics":    topics,
+            "fromBlock": to_quantity(from),
+            "toBlock":   to_quantity(to),
+        }])).await
+    }
+}
+
+pub fn to_quantity(value: u64) -> String {
+    format!("0x{:x}", value)
+}
+
+pub fn parse_quantity(value: &str) -> Result<u64, String> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| "invalid quantity: ".to_string() + value)
+}
+
+/// `0x` prefixed keccak256 of an event signature, i.e. its `topic0`.
+pub fn event_topic(signature: &str) -> String {
+    "0x".to_string() + &hex::encode(crate::utils::keccak256(signature.as_bytes()))
+}
+
+/// An address as an indexed topic, left padded to 32 bytes.
+pub fn address_topic(address: &WalletAddress) -> String {
+    "0x".to_string() + &"0".repeat(24) + &hex::encode(address.to_bytes())
+}
+
+/// The address held in the low 20 bytes of an indexed topic.
+pub fn topic_address(topic: &str) -> Result<WalletAddress, String> {
+    let bytes = hex::decode(topic.trim_start_matches("0x")).map_err(|_| "invalid topic: ".to_string() + topic)?;
+    if bytes.len() != 32 {
+        return Err("invalid topic: ".to_string() + topic);
+    }
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&bytes[12..]);
+    Ok(WalletAddress::from_bytes(&address))
+}
+
+/// Converts a big-endian hex quantity of any width (e.g. a uint256 word)
+/// into its decimal string.
+pub fn hex_to_decimal(value: &str) -> Result<String, String> {
+    let digits = value.trim_start_matches("0x");
+    let mut decimal: Vec<u8> = vec![0];
+    for c in digits.chars() {
+        let mut carry = c.to_digit(16).ok_or_else(|| "invalid hex: ".to_string() + value)?;
+        // decimal holds base-10 digits, least significant first
+        for d in decimal.iter_mut() {
+            let v = *d as u32 * 16 + carry;
+            *d = (v % 10) as u8;
+            carry = v / 10;
+        }
+        while carry > 0 {
+            decimal.push((carry % 10) as u8);
+            carry /= 10;
+        }
+    }
+    while decimal.len() > 1 && decimal.last() == Some(&0) {
+        decimal.pop();
+    }
+    Ok(decimal.iter().rev().map(|d| (b'0' + d) as char).collect())
+}
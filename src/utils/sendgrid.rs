@@ -0,0 +1,28 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+
+/// Checks SendGrid's signed event webhook: an ECDSA P-256 / SHA-256
+/// signature over the timestamp header followed by the raw body. The
+/// public key and signature are base64 DER, as SendGrid hands them out.
+pub fn verify_sendgrid_signature(public_key: &str, signature: &str, timestamp: &str, payload: &[u8]) -> bool {
+    let key = match STANDARD.decode(public_key.trim())
+        .ok()
+        .and_then(|der| VerifyingKey::from_public_key_der(&der).ok())
+    {
+        Some(key) => key,
+        None => return false,
+    };
+    let signature = match STANDARD.decode(signature.trim())
+        .ok()
+        .and_then(|der| Signature::from_der(&der).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut message = Vec::with_capacity(timestamp.len() + payload.len());
+    message.extend_from_slice(timestamp.as_bytes());
+    message.extend_from_slice(payload);
+    key.verify(&message, &signature).is_ok()
+}
@@ -0,0 +1,86 @@
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message,
+    Secp256k1,
+};
+use crate::utils::{keccak256, WalletAddress};
+
+/// EIP-191 hash of a `personal_sign` message.
+pub fn personal_message_hash(message: &str) -> [u8; 32] {
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message.as_bytes());
+    keccak256(&data)
+}
+
+/// Recovers the address that produced a 65-byte `personal_sign` signature
+/// (r || s || v, hex encoded, v either 0/1 or 27/28).
+pub fn recover_personal_sign(message: &str, signature: &str) -> Result<WalletAddress, String> {
+    let signature = signature.trim();
+    let bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(|_| "Signature is not valid hex".to_string())?;
+    if bytes.len() != 65 {
+        return Err("Signature must be 65 bytes long".to_string());
+    }
+    let v = match bytes[64] {
+        27 | 28 => bytes[64] - 27,
+        0 | 1 => bytes[64],
+        _ => return Err("Signature has an invalid recovery id".to_string()),
+    };
+    let recovery_id = RecoveryId::from_i32(v as i32)
+        .map_err(|_| "Signature has an invalid recovery id".to_string())?;
+    let signature = RecoverableSignature::from_compact(&bytes[..64], recovery_id)
+        .map_err(|_| "Signature is malformed".to_string())?;
+    let message = Message::from_slice(&personal_message_hash(message))
+        .map_err(|_| "Message hash is malformed".to_string())?;
+    let public_key = Secp256k1::verification_only()
+        .recover_ecdsa(&message, &signature)
+        .map_err(|_| "Could not recover signer".to_string())?;
+
+    // the address is the last 20 bytes of keccak256 over the 64-byte public key
+    let hash = keccak256(&public_key.serialize_uncompressed()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Ok(WalletAddress::from_bytes(&address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // web3.eth.accounts.sign("Some data", key) with the private key
+    // 0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318
+    const MESSAGE: &str = "Some data";
+    const SIGNATURE: &str = "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c";
+    const SIGNER: &str = "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23";
+
+    #[test]
+    fn message_hash() {
+        assert_eq!(
+            hex::encode(personal_message_hash(MESSAGE)),
+            "1da44b586eb0729ff70a73c326926f6ed5a25f5b056e7f47fbc6e58d86871655",
+        );
+    }
+
+    #[test]
+    fn recovers_signer() {
+        assert_eq!(recover_personal_sign(MESSAGE, SIGNATURE).unwrap().as_str(), SIGNER);
+    }
+
+    #[test]
+    fn accepts_zero_based_recovery_id() {
+        let signature = SIGNATURE[..SIGNATURE.len() - 2].to_string() + "01";
+        assert_eq!(recover_personal_sign(MESSAGE, &signature).unwrap().as_str(), SIGNER);
+    }
+
+    #[test]
+    fn other_message_recovers_other_address() {
+        let signer = recover_personal_sign("Some other data", SIGNATURE).unwrap();
+        assert_ne!(signer.as_str(), SIGNER);
+    }
+
+    #[test]
+    fn rejects_malformed_signature() {
+        assert!(recover_personal_sign(MESSAGE, "0x1234").is_err());
+        assert!(recover_personal_sign(MESSAGE, &(SIGNATURE[..SIGNATURE.len() - 2].to_string() + "05")).is_err());
+    }
+}
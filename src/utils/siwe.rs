@@ -0,0 +1,205 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use crate::utils::WalletAddress;
+
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+/// A parsed EIP-4361 (Sign-In with Ethereum) message.
+#[derive(Debug)]
+pub struct SiweMessage {
+    pub domain:          String,
+    pub address:         WalletAddress,
+    pub statement:       Option<String>,
+    pub uri:             String,
+    pub version:         String,
+    pub chain_id:        u64,
+    pub nonce:           String,
+    pub issued_at:       DateTime<FixedOffset>,
+    pub expiration_time: Option<DateTime<FixedOffset>>,
+    pub not_before:      Option<DateTime<FixedOffset>>,
+    pub request_id:      Option<String>,
+    pub resources:       Vec<String>,
+}
+
+fn tagged<'a>(line: Option<&'a str>, tag: &str) -> Result<&'a str, String> {
+    line.and_then(|l| l.strip_prefix(tag))
+        .and_then(|l| l.strip_prefix(": "))
+        .ok_or_else(|| "SIWE message is missing `".to_string() + tag + "`")
+}
+
+fn timestamp(value: &str, tag: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|_| "SIWE message has an invalid `".to_string() + tag + "`")
+}
+
+impl SiweMessage {
+    pub fn parse(message: &str) -> Result<SiweMessage, String> {
+        let mut lines = message.lines().peekable();
+
+        let domain = lines.next()
+            .and_then(|l| l.strip_suffix(PREAMBLE))
+            .ok_or_else(|| "SIWE message has an invalid header".to_string())?
+            .to_string();
+        let address = WalletAddress::parse(lines.next().unwrap_or_default())?;
+        if lines.next() != Some("") {
+            return Err("SIWE message must have an empty line after the address".to_string());
+        }
+
+        let mut statement = None;
+        if let Some(line) = lines.peek() {
+            if !line.starts_with("URI: ") {
+                statement = Some(line.to_string());
+                lines.next();
+                if lines.next() != Some("") {
+                    return Err("SIWE message must have an empty line after the statement".to_string());
+                }
+            }
+        }
+
+        let uri = tagged(lines.next(), "URI")?.to_string();
+        let version = tagged(lines.next(), "Version")?.to_string();
+        if version != "1" {
+            return Err("Unsupported SIWE version ".to_string() + &version);
+        }
+        let chain_id = tagged(lines.next(), "Chain ID")?
+            .parse::<u64>()
+            .map_err(|_| "SIWE message has an invalid `Chain ID`".to_string())?;
+        let nonce = tagged(lines.next(), "Nonce")?.to_string();
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err("SIWE message has an invalid `Nonce`".to_string());
+        }
+        let issued_at = timestamp(tagged(lines.next(), "Issued At")?, "Issued At")?;
+
+        let mut expiration_time = None;
+        let mut not_before = None;
+        let mut request_id = None;
+        let mut resources = Vec::new();
+        if lines.peek().map_or(false, |l| l.starts_with("Expiration Time: ")) {
+            expiration_time = Some(timestamp(tagged(lines.next(), "Expiration Time")?, "Expiration Time")?);
+        }
+        if lines.peek().map_or(false, |l| l.starts_with("Not Before: ")) {
+            not_before = Some(timestamp(tagged(lines.next(), "Not Before")?, "Not Before")?);
+        }
+        if lines.peek().map_or(false, |l| l.starts_with("Request ID: ")) {
+            request_id = Some(tagged(lines.next(), "Request ID")?.to_string());
+        }
+        if lines.peek() == Some(&"Resources:") {
+            lines.next();
+            while let Some(resource) = lines.peek().and_then(|l| l.strip_prefix("- ")) {
+                resources.push(resource.to_string());
+                lines.next();
+            }
+        }
+        if let Some(line) = lines.next() {
+            return Err("SIWE message has an unexpected line: ".to_string() + line);
+        }
+
+        Ok(SiweMessage {
+            domain:          domain,
+            address:         address,
+            statement:       statement,
+            uri:             uri,
+            version:         version,
+            chain_id:        chain_id,
+            nonce:           nonce,
+            issued_at:       issued_at,
+            expiration_time: expiration_time,
+            not_before:      not_before,
+            request_id:      request_id,
+            resources:       resources,
+        })
+    }
+
+    /// Checks the message is addressed to `domain`, names a URI under
+    /// `origin` and the expected chain, and is currently valid.
+    pub fn validate(&self, domain: &str, origin: &str, chain_id: u64) -> Result<(), String> {
+        if self.domain != domain {
+            return Err("SIWE message is for another domain".to_string());
+        }
+        let origin = origin.trim_end_matches('/');
+        if self.uri != origin && !self.uri.starts_with(&(origin.to_string() + "/")) {
+            return Err("SIWE message is for another URI".to_string());
+        }
+        if self.chain_id != chain_id {
+            return Err("SIWE message is for another chain".to_string());
+        }
+        let now = Utc::now();
+        if let Some(expiration_time) = self.expiration_time {
+            if expiration_time < now {
+                return Err("SIWE message has expired".to_string());
+            }
+        }
+        if let Some(not_before) = self.not_before {
+            if not_before > now {
+                return Err("SIWE message is not valid yet".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "example.com wants you to sign in with your Ethereum account:
+0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed
+
+Sign in to the token sale.
+
+URI: https://example.com/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756abcdEF
+Issued At: 2021-09-30T16:25:24Z
+Expiration Time: 2999-01-01T00:00:00Z
+Resources:
+- https://example.com/terms";
+
+    #[test]
+    fn parses_message() {
+        let message = SiweMessage::parse(MESSAGE).unwrap();
+        assert_eq!(message.domain, "example.com");
+        assert_eq!(message.address.as_str(), "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(message.statement.as_deref(), Some("Sign in to the token sale."));
+        assert_eq!(message.uri, "https://example.com/login");
+        assert_eq!(message.chain_id, 1);
+        assert_eq!(message.nonce, "32891756abcdEF");
+        assert!(message.not_before.is_none());
+        assert_eq!(message.resources, vec!["https://example.com/terms".to_string()]);
+    }
+
+    #[test]
+    fn validates_domain() {
+        let message = SiweMessage::parse(MESSAGE).unwrap();
+        assert!(message.validate("example.com", "https://example.com", 1).is_ok());
+        assert!(message.validate("evil.com", "https://example.com", 1).is_err());
+    }
+
+    #[test]
+    fn validates_uri_and_chain() {
+        let message = SiweMessage::parse(MESSAGE).unwrap();
+        assert!(message.validate("example.com", "https://example.com/", 1).is_ok());
+        assert!(message.validate("example.com", "https://example.co", 1).is_err());
+        assert!(message.validate("example.com", "http://example.com", 1).is_err());
+        assert!(message.validate("example.com", "https://example.com", 5).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_message() {
+        let message = SiweMessage::parse(&MESSAGE.replace("2999-01-01", "2000-01-01")).unwrap();
+        assert!(message.validate("example.com", "https://example.com", 1).is_err());
+    }
+
+    #[test]
+    fn parses_message_without_statement() {
+        let message = SiweMessage::parse(&MESSAGE.replace("Sign in to the token sale.\n\n", "")).unwrap();
+        assert!(message.statement.is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_message() {
+        assert!(SiweMessage::parse(&MESSAGE.replace("Version: 1", "Version: 2")).is_err());
+        assert!(SiweMessage::parse(&MESSAGE.replace("Nonce: 32891756abcdEF", "Nonce: 123")).is_err());
+        assert!(SiweMessage::parse(&(MESSAGE.to_string() + "\nextra")).is_err());
+    }
+}
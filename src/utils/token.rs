@@ -0,0 +1,26 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(purpose: &str, value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(crate::vars::secret_key().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(purpose.as_bytes());
+    mac.update(b":");
+    mac.update(value.as_bytes());
+    mac
+}
+
+/// A hex HMAC of `value`, bound to `purpose` so a token issued for one link
+/// cannot be replayed against another.
+pub fn sign_token(purpose: &str, value: &str) -> String {
+    hex::encode(mac(purpose, value).finalize().into_bytes())
+}
+
+pub fn verify_token(purpose: &str, value: &str, token: &str) -> bool {
+    match hex::decode(token) {
+        Ok(bytes) => mac(purpose, value).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
@@ -5,3 +5,211 @@ pub fn secret_key() -> String {
   dotenv().ok();
   var("SECRET_KEY").unwrap_or_else(|_| "0123".repeat(8))
 }
+
+pub fn api_url() -> String {
+  dotenv().ok();
+  var("API_URL").unwrap_or_else(|_| "https://api.bjustcoin.com".to_string())
+}
+
+pub fn dashboard_url() -> String {
+  dotenv().ok();
+  var("DASHBOARD_URL").unwrap_or_else(|_| "https://dashboard.bjustcoin.com".to_string())
+}
+
+pub fn support_email() -> String {
+  dotenv().ok();
+  var("SUPPORT_EMAIL").unwrap_or_else(|_| "Corporate@bjustcoin.com".to_string())
+}
+
+/// The official ICO link sent to subscribers.
+pub fn ico_url() -> String {
+  dotenv().ok();
+  var("ICO_URL").unwrap_or_else(|_| "https://etherscan.io/address/0xf86082F6bf8BD9FFC02755f65FC3d7eC7d1A0ffc".to_string())
+}
+
+pub fn email_key() -> Option<String> {
+  dotenv().ok();
+  var("EMAIL_KEY").ok().filter(|v| !v.is_empty())
+}
+
+pub fn sendgrid_webhook_key() -> Option<String> {
+  dotenv().ok();
+  var("SENDGRID_WEBHOOK_KEY").ok().filter(|v| !v.is_empty())
+}
+
+pub fn mail_transport() -> String {
+  dotenv().ok();
+  var("MAIL_TRANSPORT").unwrap_or_else(|_| "sendgrid".to_string())
+}
+
+pub fn mail_dir() -> String {
+  dotenv().ok();
+  var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string())
+}
+
+pub fn mail_from() -> String {
+  dotenv().ok();
+  var("MAIL_FROM").unwrap_or_else(|_| "no-reply@bjustcoin.com".to_string())
+}
+
+pub fn mail_from_name() -> String {
+  dotenv().ok();
+  var("MAIL_FROM_NAME").unwrap_or_else(|_| "BJustcoin Team".to_string())
+}
+
+pub fn campaign_rate() -> u32 {
+  dotenv().ok();
+  var("CAMPAIGN_RATE")
+    .ok()
+    .and_then(|v| v.parse::<u32>().ok())
+    .unwrap_or(60)
+}
+
+pub fn smtp_host() -> Option<String> {
+  dotenv().ok();
+  var("SMTP_HOST").ok()
+}
+
+pub fn smtp_port() -> u16 {
+  dotenv().ok();
+  var("SMTP_PORT")
+    .ok()
+    .and_then(|v| v.parse::<u16>().ok())
+    .unwrap_or(587)
+}
+
+pub fn smtp_user() -> Option<String> {
+  dotenv().ok();
+  var("SMTP_USER").ok()
+}
+
+pub fn smtp_password() -> Option<String> {
+  dotenv().ok();
+  var("SMTP_PASSWORD").ok()
+}
+
+pub fn siwe_domain() -> String {
+  dotenv().ok();
+  var("SIWE_DOMAIN").unwrap_or_else(|_| "dashboard.bjustcoin.com".to_string())
+}
+
+/// Origin SIWE messages must name as their `URI`.
+pub fn siwe_uri() -> String {
+  dotenv().ok();
+  var("SIWE_URI").unwrap_or_else(|_| "https://".to_string() + &siwe_domain())
+}
+
+pub fn siwe_chain_id() -> u64 {
+  dotenv().ok();
+  var("SIWE_CHAIN_ID")
+    .ok()
+    .and_then(|v| v.parse::<u64>().ok())
+    .unwrap_or(1)
+}
+
+pub fn token_decimals() -> u32 {
+  dotenv().ok();
+  var("TOKEN_DECIMALS")
+    .ok()
+    .and_then(|v| v.parse::<u32>().ok())
+    .unwrap_or(18)
+}
+
+pub fn rpc_url() -> String {
+  dotenv().ok();
+  var("ETH_RPC_URL").unwrap_or_else(|_| "http://127.0.0.1:8545".to_string())
+}
+
+pub fn rpc_key() -> String {
+  dotenv().ok();
+  var("ETH_RPC_KEY").unwrap_or_default()
+}
+
+pub fn token_contract() -> Option<String> {
+  dotenv().ok();
+  var("BJC_CONTRACT").ok()
+}
+
+pub fn sale_contract() -> Option<String> {
+  dotenv().ok();
+  var("SALE_CONTRACT").ok()
+}
+
+pub fn purchase_event() -> String {
+  dotenv().ok();
+  var("PURCHASE_EVENT").unwrap_or_else(|_| "TokensPurchased(address,uint256)".to_string())
+}
+
+pub fn chain_start_block() -> i64 {
+  dotenv().ok();
+  var("CHAIN_START_BLOCK")
+    .ok()
+    .and_then(|v| v.parse::<i64>().ok())
+    .unwrap_or(0)
+}
+
+pub fn chain_confirmations() -> u64 {
+  dotenv().ok();
+  var("CHAIN_CONFIRMATIONS")
+    .ok()
+    .and_then(|v| v.parse::<u64>().ok())
+    .unwrap_or(12)
+}
+
+pub fn chain_holder_stage() -> String {
+  dotenv().ok();
+  var("CHAIN_HOLDER_STAGE").unwrap_or_else(|_| "On-chain".to_string())
+}
+
+/// The account buyers pay to. Required: the server refuses to start
+/// without it, see `main`.
+pub fn payment_address() -> Option<String> {
+  dotenv().ok();
+  var("PAYMENT_ADDRESS").ok()
+}
+
+pub fn payment_currency() -> String {
+  dotenv().ok();
+  var("PAYMENT_CURRENCY").unwrap_or_else(|_| "USDT".to_string())
+}
+
+/// Token contract of `PAYMENT_CURRENCY`; its transfers to the payment
+/// address confirm payments.
+pub fn payment_token_contract() -> Option<String> {
+  dotenv().ok();
+  var("PAYMENT_TOKEN_CONTRACT").ok()
+}
+
+pub fn payment_token_decimals() -> u32 {
+  dotenv().ok();
+  var("PAYMENT_TOKEN_DECIMALS")
+    .ok()
+    .and_then(|v| v.parse::<u32>().ok())
+    .unwrap_or(6)
+}
+
+pub fn payment_ttl_hours() -> i64 {
+  dotenv().ok();
+  var("PAYMENT_TTL_HOURS")
+    .ok()
+    .and_then(|v| v.parse::<i64>().ok())
+    .unwrap_or(72)
+}
+
+pub fn database_url() -> String {
+  dotenv().ok();
+  var("DATABASE_URL").expect("DATABASE_URL must be set")
+}
+
+/// Postgres channel the websocket servers of all instances share.
+pub fn ws_channel() -> String {
+  dotenv().ok();
+  var("WS_CHANNEL").unwrap_or_else(|_| "ws_events".to_string())
+}
+
+/// Address of the reverse proxy whose X-Forwarded-For / Forwarded headers
+/// are trusted for the client address.
+pub fn trusted_proxy() -> Option<String> {
+  dotenv().ok();
+  var("TRUSTED_PROXY").ok().filter(|v| !v.is_empty())
+}
@@ -24,11 +24,13 @@ use crate::diesel::{
     RunQueryDsl,
     Connection,
 };
-use crate::utils::establish_connection;
+use crate::mailer::{self, Recipient};
+use crate::websocket::{disconnect_user, publish_to_admins, publish_to_topic, EventType, Topic};
 
 
 pub fn admin_routes(config: &mut web::ServiceConfig) {
     config.route("/get_holders/", web::get().to(get_holders));
+    config.route("/get_holders_stats/", web::get().to(get_holders_stats));
     config.route("/get_small_users/", web::get().to(get_small_users));
     config.route("/get_users/", web::get().to(get_users)); 
     config.route("/get_admins/", web::get().to(get_admins));
@@ -49,17 +51,47 @@ pub fn admin_routes(config: &mut web::ServiceConfig) {
     config.route("/create_admin/", web::post().to(create_admin));
     config.route("/drop_admin/", web::post().to(drop_admin));
     config.route("/agree_application/", web::post().to(agree_application));
+    config.route("/reject_application/", web::post().to(reject_application));
     config.route("/create_suggest_item/", web::post().to(create_suggest_item));
     config.route("/create_log/", web::post().to(create_log));
     config.route("/create_holders/", web::post().to(create_holders));
+    config.route("/edit_holder/", web::post().to(edit_holder));
     config.route("/delete_holder/", web::post().to(delete_holder));
     config.route("/send_mail/", web::post().to(send_mail));
     config.route("/subscribe/", web::post().to(subscribe));
+    config.route("/confirm_subscription/", web::get().to(confirm_subscription));
+    config.route("/unsubscribe/", web::get().to(unsubscribe));
+    config.route("/unsubscribe/", web::post().to(unsubscribe));
 }
 
 pub async fn get_holders(req: HttpRequest) -> Json<crate::models::HolderRespData> {
+    if is_signed_in(&req) {
         let page = crate::utils::get_page(&req);
-        Json(crate::models::Holder::get_list(page.into(), Some(20)))
+        let filter = web::Query::<crate::models::HolderFilter>::from_query(&req.query_string())
+            .map(|f| f.into_inner())
+            .unwrap_or_default();
+        Json(crate::models::Holder::get_list(&filter, page.into(), Some(20)))
+    }
+    else {
+        Json(crate::models::HolderRespData {
+            data: Vec::new(),
+            next: 0,
+        })
+    }
+}
+pub async fn get_holders_stats(req: HttpRequest) -> Result<Json<crate::models::HolderStats>, crate::errors::Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub top: Option<i64>,
+    }
+    if !is_signed_in(&req) {
+        return Err(crate::errors::Error::Unauthorized);
+    }
+    let top = web::Query::<Params>::from_query(&req.query_string())
+        .ok()
+        .and_then(|p| p.top);
+    let stats = web::block(move || crate::models::Holder::get_stats(crate::utils::get_limit(top, 10))).await?;
+    Ok(Json(stats))
 }
 pub async fn get_new_applications(req: HttpRequest) -> Json<crate::models::SuggestRespData> {
     if is_signed_in(&req) {
@@ -231,7 +263,10 @@ pub struct ItemId {
 pub async fn block_user(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
     if is_signed_in(&req) {
         let _request_user = get_current_user(&req);
-        _request_user.create_user_block(data.id);
+        if _request_user.create_user_block(data.id).is_ok() {
+            disconnect_user(data.id);
+            publish_to_admins(EventType::Block, data.id, serde_json::json!({ "admin": false }));
+        }
     }
     HttpResponse::Ok()
 }
@@ -246,7 +281,10 @@ pub async fn unblock_user(req: HttpRequest, data: Json<ItemId>) -> impl Responde
 pub async fn block_admin(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
     if is_signed_in(&req) {
         let _request_user = get_current_user(&req);
-        _request_user.create_admin_block(data.id);
+        if _request_user.create_admin_block(data.id).is_ok() {
+            disconnect_user(data.id);
+            publish_to_admins(EventType::Block, data.id, serde_json::json!({ "admin": true }));
+        }
     }
     HttpResponse::Ok()
 }
@@ -268,7 +306,10 @@ pub async fn create_admin(req: HttpRequest, data: Json<ItemId>) -> impl Responde
 pub async fn drop_admin(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
     if is_signed_in(&req) {
         let _request_user = get_current_user(&req);
-        _request_user.delete_admin_block(data.id);
+        if _request_user.delete_admin(data.id).is_ok() {
+            // their sessions still carry admin rights
+            disconnect_user(data.id);
+        }
     }
     HttpResponse::Ok()
 }
@@ -284,107 +325,101 @@ pub struct ReqWallet {
     pub id:        i32,
     pub tokens:    String,
     pub ico_stage: i16,
+    /// what the applicant pays, in the payment currency
+    pub amount:    String,
 }
-pub async fn agree_application(req: HttpRequest, data: Json<ReqWallet>) -> impl Responder {
+pub async fn agree_application(req: HttpRequest, data: Json<ReqWallet>) -> Result<HttpResponse, crate::errors::Error> {
     if is_signed_in(&req) {
         let _request_user = get_current_user(&req);
         if _request_user.is_superuser() {
-            crate::models::SuggestItem::agree_application(data.id, data.tokens.clone(), data.ico_stage);
+            crate::models::SuggestItem::agree_application(data.id, data.tokens.clone(), data.ico_stage, data.amount.clone())?;
             let user_data = crate::models::SuggestItem::get_user_data(data.id);
-            
-            dotenv::dotenv().ok(); 
-            let api_key = std::env::var("EMAIL_KEY")
-                .expect("EMAIL_KEY must be set");
-            let sg = sendgrid::SGClient::new(api_key); 
-            let mut x_smtpapi = String::new();
-            x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-            // mail for Beatrice
-            let text = "<div><strong>Dear ".to_string() + &user_data.first_name + &"</strong><br /><br />Congratulations! Your purchase of <strong>BJustCoin (BJC)</strong> has been <strong>approved</strong>. You’re just one step away from securing your requested amount.<br /><br />To complete your transaction, please follow the link below:<br /><br /><a href='https://dashboard.bjustcoin.com/profile/' target='_blank'>🔗 Complete Your Purchase Now</a><br /><br />Once your purchase is finalized, we’ll handle the rest and ensure your BJC is securely delivered to your wallet.<br /><br />If you have any questions or need assistance, feel free to reach out to our support team at <strong>Corporate@bjustcoin.com</strong><br /><br />Thank you for choosing <strong>BJustCoin</strong>—welcome to the future of digital transactions!<br /><br />Best regards,<br /><strong>The BJustCoin Team</strong></div>".to_string();
             let name = user_data.first_name.clone() + &" ".to_string() + &user_data.last_name;
-            let mail_info = sendgrid::Mail::new() 
-                .add_to(sendgrid::Destination {
-                    address: &user_data.email,
-                    name: &name,
-                })
-                .add_from("no-reply@bjustcoin.com")
-                .add_subject("Your BJustCoin Purchase is Approved – Complete Your Transaction Now!")
-                .add_html(&text)
-                .add_from_name("BJustcoin Team")
-                .add_header("x-cool".to_string(), "indeed")
-                .add_x_smtpapi(&x_smtpapi);
-
-            match sg.send(mail_info).await {
-                Err(err) => println!("Error: {}", err),
-                Ok(body) => println!("Response: {:?}", body),
-            };
-            println!("mail send!");
+            let locale = crate::models::User::get_user_with_email(user_data.email.clone())
+                .map(|u| u.locale)
+                .unwrap_or_else(|_| crate::i18n::DEFAULT_LOCALE.to_string());
+            mailer::queue_or_log (
+                "application_approved",
+                &Recipient::new(&user_data.email, &name).with_locale(&locale),
+                &serde_json::json!({ "first_name": user_data.first_name }),
+            );
+            mailer::notify_admins (
+                "approval",
+                "application_approved_admin",
+                serde_json::json!({
+                    "first_name": user_data.first_name,
+                    "last_name":  user_data.last_name,
+                    "tokens":     data.tokens,
+                    "stage":      data.ico_stage,
+                    "approver":   _request_user.email,
+                }),
+            );
+            let event = serde_json::json!({
+                "first_name": user_data.first_name,
+                "last_name":  user_data.last_name,
+                "tokens":     data.tokens,
+                "stage":      data.ico_stage,
+            });
+            publish_to_admins(EventType::Approval, data.id, event.clone());
+            publish_to_topic(Topic::Application(data.id), EventType::Approval, data.id, event);
+            // every signed-in user may follow a stage, so it only gets totals
+            publish_to_topic(Topic::Stage(data.ico_stage), EventType::Approval, 0, serde_json::json!({
+                "stage":    data.ico_stage,
+                "approved": crate::models::SuggestItem::count_approved(data.ico_stage),
+            }));
         }
     }
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn reject_application(req: HttpRequest, data: Json<ItemId>) -> Result<HttpResponse, crate::errors::Error> {
+    if !is_signed_in(&req) {
+        return Err(crate::errors::Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_superuser() {
+        return Err(crate::errors::Error::Forbidden);
+    }
+    crate::models::SuggestItem::reject_application(data.id, &_request_user)?;
+    // the applicant is told through their notification; who rejected is
+    // for admins only
+    publish_to_admins(EventType::Rejection, data.id, serde_json::json!({ "rejecter": _request_user.email }));
+    Ok(HttpResponse::Ok().finish())
 }
 
 pub async fn create_suggest_item(req: HttpRequest, data: Json<crate::models::NewSuggestJson>) -> impl Responder {
     if is_signed_in(&req) {
         let _request_user = get_current_user(&req);
-    
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("EMAIL_KEY")
-            .expect("EMAIL_KEY must be set");
-        let sg = sendgrid::SGClient::new(api_key); 
-        let mut x_smtpapi = String::new();
-        x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
 
-        // mail for Beatrice
-        let text = "A new BJustCoin purchase interest has triggered. Link to the list - ".to_string()
-            + &"https://dashboard.bjustcoin.com/suggest_items/".to_string();
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: "Beatrice.OBrien@justlaw.com",
-                name: "Beatrice OBrien",
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("New Application for Token purchase")
-            .add_html(&text)
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
-
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-        println!("mail send!");
-
-        // mail for request user
-        let text = "Your application for token purchase was submitted! Thank you for your interest! We’re thrilled by the incredible response and appreciate your enthusiasm. Due to the high volume of demand, we are currently experiencing a slight delay in processing orders. Rest assured, our team is working tirelessly to get your purchases to you as quickly as possible. Thank you for your patience and support—it means the world to us. Stay tuned for updates, and we can’t wait for you to complete your purchase of BJustCoin.".to_string();
-        let first_name = _request_user.first_name.clone();
-        let last_name = _request_user.last_name.clone();
-        let email = _request_user.email.clone();
-        let name = first_name + &" ".to_string() + &last_name;
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: &email,
-                name: &name,
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("Your application was submitted!")
-            .add_html(&text)
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
+        mailer::notify_admins (
+            "new_application",
+            "new_application",
+            serde_json::json!({
+                "first_name": _request_user.first_name,
+                "last_name":  _request_user.last_name,
+            }),
+        );
 
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-        println!("mail send!");
-        crate::models::SuggestItem::create (
+        mailer::queue_or_log (
+            "application_submitted",
+            &_request_user.get_recipient(),
+            &serde_json::json!({ "first_name": _request_user.first_name }),
+        );
+        let item = crate::models::SuggestItem::create (
             data, 
             _request_user.first_name.clone(),
             _request_user.last_name.clone(),
             _request_user.email.clone()
         );
+        publish_to_admins (
+            EventType::Application,
+            item.id,
+            serde_json::json!({
+                "first_name": item.first_name,
+                "last_name":  item.last_name,
+                "tokens":     item.tokens,
+            }),
+        );
     }
     HttpResponse::Ok()
 }
@@ -395,12 +430,26 @@ pub async fn create_log(req: HttpRequest, data: Json<crate::models::NewLogJson>)
     }
     HttpResponse::Ok()
 }
-pub async fn create_holders(req: HttpRequest, data: Json<Vec<crate::models::NewHolder>>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        crate::models::Holder::create(data);
+pub async fn create_holders(req: HttpRequest, data: Json<Vec<crate::models::HolderJson>>) -> Result<Json<crate::models::HolderImportReport>, crate::errors::Error> {
+    if !is_signed_in(&req) {
+        return Err(crate::errors::Error::Unauthorized);
     }
-    HttpResponse::Ok()
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(crate::errors::Error::Forbidden);
+    }
+    Ok(Json(crate::models::Holder::create(data)))
+}
+pub async fn edit_holder(req: HttpRequest, data: Json<crate::models::EditHolderJson>) -> Result<HttpResponse, crate::errors::Error> {
+    if !is_signed_in(&req) {
+        return Err(crate::errors::Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(crate::errors::Error::Forbidden);
+    }
+    crate::models::Holder::edit(data.id, data.count2.clone(), data.stage.clone())?;
+    Ok(HttpResponse::Ok().finish())
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct DataId { 
@@ -422,42 +471,47 @@ pub struct SendMailJson {
     pub last_name:  String,
     pub email:      String,
 }   
-pub async fn send_mail(req: HttpRequest, data: Json<SendMailJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _connection = establish_connection();
-        let _request_user = get_current_user(&req);
-
-        let first_name: String;
-        let last_name: String;
-        let email: String;
-    
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("EMAIL_KEY")
-            .expect("EMAIL_KEY must be set");
-        let sg = sendgrid::SGClient::new(api_key); 
-        let mut x_smtpapi = String::new();
-        x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-        let name = data.first_name.clone() + &" ".to_string() + &data.last_name;
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: &data.email,
-                name:    &name,
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject(&data.subtitle)
-            .add_html(&data.text)
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
-
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-        println!("mail send!");
-    }
-    HttpResponse::Ok()
+/// Sends an admin-authored email. The html is sanitized and only
+/// registered users and confirmed subscribers can be addressed.
+pub async fn send_mail(req: HttpRequest, data: Json<SendMailJson>) -> Result<HttpResponse, crate::errors::Error> {
+    if !is_signed_in(&req) {
+        return Err(crate::errors::Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(crate::errors::Error::Forbidden);
+    }
+    if data.subtitle.trim().is_empty() || data.text.trim().is_empty() {
+        return Err(crate::errors::Error::BadRequest("Subject and text are required".to_string()));
+    }
+
+    let name = data.first_name.clone() + &" ".to_string() + &data.last_name;
+    let (recipient, target_id) = match User::get_user_with_email(data.email.clone()) {
+        Ok(user) => (user.get_recipient(), Some(user.id)),
+        Err(_) => match crate::models::Subscriber::get_with_email(&data.email) {
+            Some(s) if s.status == crate::models::SUBSCRIBER_CONFIRMED => (Recipient::new(&s.email, &name), None),
+            _ => return Err(crate::errors::Error::BadRequest("The recipient is not a registered user or subscriber".to_string())),
+        },
+    };
+
+    let html = mailer::sanitize_html(&data.text);
+    mailer::queue (
+        "custom",
+        &recipient,
+        &serde_json::json!({
+            "subject": data.subtitle,
+            "html":    html,
+            "text":    mailer::html_to_text(&html),
+        }),
+    )?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "sent an email ".to_string() + &mailer::content_hash(&data.subtitle, &html),
+            target_id: target_id,
+        })
+    });
+    Ok(HttpResponse::Ok().finish())
 }
 
 
@@ -465,66 +519,59 @@ pub async fn send_mail(req: HttpRequest, data: Json<SendMailJson>) -> impl Respo
 pub struct SendSubscribeMailJson {
     pub email: String,
 } 
-pub async fn subscribe(req: HttpRequest, data: Json<SendSubscribeMailJson>) -> impl Responder {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("EMAIL_KEY")
-            .expect("EMAIL_KEY must be set");
-        let sg = sendgrid::SGClient::new(api_key); 
-        let mut x_smtpapi = String::new();
-        x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-        // mail for subscriber
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: &data.email,
-                name:    "BJustCoin Community Member",
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("Join Us in the Seed Round of BJustCoin ICO!")
-            .add_html("Dear BJustCoin Community Member, We’re thrilled to have you as part of our exciting journey! We are currently in the Seed Round of our ICO, and this is your opportunity to be an integral part of the growth and future of BJustCoin. Feel free to join us and make your purchase via the official ICO link: https://etherscan.io/address/0xf86082F6bf8BD9FFC02755f65FC3d7eC7d1A0ffc. Your support and belief in our vision mean the world to us, and we’re excited to build the future together with you. Let’s make it happen! Warm regards, The BJustCoin Team")
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
-
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-
-        // mail for Beatrice
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: "beatrice.obrien@justlaw.com",
-                name:    "Beatrice O'Brien",
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("ICO Email Monitoring Notification")
-            .add_html("Dear Beatrice. You are receiving this notification as part of your role in monitoring ICO-related communications for BJustCoin.")
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
-
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
+/// Starts the double opt-in: the address only joins the list once the
+/// link in the confirmation email is followed.
+pub async fn subscribe(req: HttpRequest, data: Json<SendSubscribeMailJson>) -> Result<HttpResponse, crate::errors::Error> {
+    let ip = crate::utils::client_ip(&req);
+    let subscriber = crate::models::Subscriber::create(&data.email, ip)?;
+    if subscriber.status != crate::models::SUBSCRIBER_CONFIRMED {
+        mailer::queue_or_log (
+            "subscribe_confirm",
+            &Recipient::new(&subscriber.email, "BJustCoin Community Member")
+                .with_locale(&crate::i18n::request_locale(&req).await),
+            &serde_json::json!({
+                "email":       subscriber.email,
+                "confirm_url": subscriber.confirm_url(),
+            }),
+        );
+    }
+    Ok(HttpResponse::Ok().finish())
+}
 
-        // mail for Colin
-        let mail_info = sendgrid::Mail::new() 
-            .add_to(sendgrid::Destination {
-                address: "colin@bjustcoin.com",
-                name:    "Colin Martin",
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("ICO Email Monitoring Notification")
-            .add_html("Dear Colin. You are receiving this notification as part of your role in monitoring ICO-related communications for BJustCoin.")
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SubscriberTokenJson {
+    pub id:     i32,
+    pub issued: Option<i64>,
+    pub token:  String,
+}
+pub async fn confirm_subscription(req: HttpRequest, data: web::Query<SubscriberTokenJson>) -> Result<HttpResponse, crate::errors::Error> {
+    let locale = crate::i18n::request_locale(&req).await;
+    let (subscriber, confirmed_now) = crate::models::Subscriber::confirm(data.id, data.issued.unwrap_or(0), &data.token)?;
+    if confirmed_now {
+        if let Err(err) = mailer::queue_with_headers (
+            "subscribe",
+            &Recipient::new(&subscriber.email, "BJustCoin Community Member").with_locale(&locale),
+            &serde_json::json!({ "unsubscribe_url": subscriber.unsubscribe_url() }),
+            subscriber.unsubscribe_headers(),
+        ) {
+            println!("mail subscribe to {} failed: {}", subscriber.email, err);
+        }
+        mailer::notify_admins (
+            "new_subscriber",
+            "subscribe_monitoring",
+            serde_json::json!({ "email": subscriber.email }),
+        );
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": crate::i18n::t(&locale, "Your subscription is confirmed"),
+    })))
+}
 
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-    HttpResponse::Ok()
-}
\ No newline at end of file
+/// Serves both the link in the email (GET) and RFC 8058 one-click
+/// unsubscribe from mail clients (POST).
+pub async fn unsubscribe(req: HttpRequest, data: web::Query<SubscriberTokenJson>) -> Result<HttpResponse, crate::errors::Error> {
+    crate::models::Subscriber::unsubscribe(data.id, &data.token)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": crate::i18n::t(&crate::i18n::request_locale(&req).await, "You are unsubscribed"),
+    })))
+}
@@ -14,9 +14,13 @@ use crate::models::{
     SessionUser, 
     UserWallet,
     EmailVerificationToken, 
-    EmailVerificationTokenMessage
+    EmailVerificationTokenMessage,
+    SiweNonce,
+    SiweLoginJson,
 };
-use crate::errors::AuthError;
+use crate::errors::{AuthError, Error};
+use crate::mailer::{self, Recipient};
+use crate::i18n;
 use chrono::Utc; 
 
 
@@ -26,6 +30,9 @@ pub fn auth_routes(config: &mut web::ServiceConfig) {
     config.route("/login/", web::post().to(login));
     config.route("/invite/", web::post().to(invite));
     config.route("/invite_reset/", web::post().to(invite_reset));
+    config.route("/siwe_nonce/", web::get().to(siwe_nonce));
+    config.route("/siwe_login/", web::post().to(siwe_login));
+    config.route("/set_locale/", web::post().to(set_locale));
 }
 
 
@@ -52,14 +59,15 @@ struct EmailUser {
     email: String,
 } 
 
-async fn invite(body: web::Json<EmailUserReq>) -> Result<HttpResponse, ApiError> {
+async fn invite(req: HttpRequest, body: web::Json<EmailUserReq>) -> Result<HttpResponse, ApiError> {
     let body = body.into_inner();
+    let locale = i18n::request_locale(&req).await;
 
     let user_some = User::get_user_with_email(body.email.clone()); 
     if user_some.is_ok() {
         return Ok(HttpResponse::Ok().json(serde_json::json!(
             EmailResp{
-                message: "The profile already exists by such mail.".to_string(),
+                message: i18n::t(&locale, "The profile already exists by such mail."),
             }
         )));
     }
@@ -70,39 +78,21 @@ async fn invite(body: web::Json<EmailUserReq>) -> Result<HttpResponse, ApiError>
     let token = EmailVerificationToken::create(token_data.clone()).expect("E.");
     let token_string = hex::encode(token.id);
     println!("{}", token_string);
-    dotenv::dotenv().ok();
-    let api_key = std::env::var("EMAIL_KEY")
-        .expect("EMAIL_KEY must be set");
-    let sg = sendgrid::SGClient::new(api_key); 
-    let mut x_smtpapi = String::new();
-    x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-    let text = "Our confirmation code - <strong>".to_owned() + &token_string.to_string() + &"</strong>".to_string();
-    let mail_info = sendgrid::Mail::new()
-        .add_to(sendgrid::Destination {
-            address: &body.email,
-            name: &body.name,
-        })
-        .add_from("no-reply@bjustcoin.com")
-        .add_subject("Email confirmation")
-        .add_html(&text)
-        .add_from_name("BJustcoin Team")
-        .add_header("x-cool".to_string(), "indeed")
-        .add_x_smtpapi(&x_smtpapi);
-
-    match sg.send(mail_info).await {
-        Err(err) => println!("Error: {}", err),
-        Ok(body) => println!("Response: {:?}", body),
-    };
+    mailer::queue_or_log (
+        "invite",
+        &Recipient::new(&body.email, &body.name).with_locale(&locale),
+        &serde_json::json!({ "code": token_string }),
+    );
     Ok(HttpResponse::Ok().json(serde_json::json!(
         EmailResp{
-            message: "Verification email sent".to_string(),
+            message: i18n::t(&locale, "Verification email sent"),
         }
     )))
 }
 
-async fn invite_reset(body: web::Json<EmailUserReq>) -> Result<HttpResponse, ApiError> {
+async fn invite_reset(req: HttpRequest, body: web::Json<EmailUserReq>) -> Result<HttpResponse, ApiError> {
     let body = body.into_inner();
+    let locale = i18n::request_locale(&req).await;
 
     let token_data = EmailVerificationTokenMessage {
         id:    None, 
@@ -111,33 +101,14 @@ async fn invite_reset(body: web::Json<EmailUserReq>) -> Result<HttpResponse, Api
     let token = EmailVerificationToken::create(token_data.clone()).expect("E.");
     let token_string = hex::encode(token.id);
     println!("{}", token_string);
-    dotenv::dotenv().ok();
-    let api_key = std::env::var("EMAIL_KEY")
-        .expect("EMAIL_KEY must be set");
-    let sg = sendgrid::SGClient::new(api_key); 
-    let mut x_smtpapi = String::new();
-    x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-    let text = "Our confirmation code - <strong>".to_owned() + &token_string.to_string() + &"</strong>".to_string();
-    let mail_info = sendgrid::Mail::new()
-        .add_to(sendgrid::Destination {
-            address: &body.email,
-            name: &body.name,
-        })
-        .add_from("no-reply@bjustcoin.com")
-        .add_subject("Email confirmation")
-        .add_html(&text)
-        .add_from_name("BJustcoin Team")
-        .add_header("x-cool".to_string(), "indeed")
-        .add_x_smtpapi(&x_smtpapi);
-
-    match sg.send(mail_info).await {
-        Err(err) => println!("Error: {}", err),
-        Ok(body) => println!("Response: {:?}", body),
-    };
+    mailer::queue_or_log (
+        "invite",
+        &Recipient::new(&body.email, &body.name).with_locale(&locale),
+        &serde_json::json!({ "code": token_string }),
+    );
     Ok(HttpResponse::Ok().json(serde_json::json!(
         EmailResp{
-            message: "Verification email sent".to_string(),
+            message: i18n::t(&locale, "Verification email sent"),
         }
     )))
 }
@@ -243,7 +214,49 @@ pub async fn login(req: HttpRequest, data: Json<LoginUser2>) -> Json<AuthResp2>
     }
 }
 
-pub async fn process_signup(data: Json<NewUserJson>) -> Json<AuthResp2> {
+#[derive(Debug, Serialize)]
+pub struct SiweNonceResp {
+    pub nonce:    String,
+    pub domain:   String,
+    pub uri:      String,
+    pub chain_id: u64,
+    pub expires:  chrono::NaiveDateTime,
+}
+
+pub async fn siwe_nonce() -> Result<Json<SiweNonceResp>, Error> {
+    let nonce = SiweNonce::create();
+    Ok(Json(SiweNonceResp {
+        nonce:    nonce.nonce,
+        domain:   crate::vars::siwe_domain(),
+        uri:      crate::vars::siwe_uri(),
+        chain_id: crate::vars::siwe_chain_id(),
+        expires:  nonce.expires,
+    }))
+}
+
+pub async fn siwe_login(req: HttpRequest, data: Json<SiweLoginJson>) -> Result<Json<AuthResp2>, Error> {
+    let request_user = match crate::utils::is_signed_in(&req) {
+        true => Some(crate::utils::get_current_user(&req)),
+        false => None,
+    };
+    let _user = SiweNonce::login(&data, request_user)?;
+    if _user.is_user_in_block() || _user.is_admin_in_block() {
+        return Err(Error::Forbidden);
+    }
+    Ok(Json(AuthResp2 {
+        id:         _user.id,
+        first_name: _user.first_name.clone(),
+        last_name:  _user.last_name.clone(),
+        email:      _user.email.clone(),
+        perm:       _user.perm,
+        image:      _user.image.clone(),
+        phone:      _user.phone.clone(),
+        uuid:       _user.uuid.clone(),
+        white_list: _user.get_user_wallets(),
+    }))
+}
+
+pub async fn process_signup(req: HttpRequest, data: Json<NewUserJson>) -> Json<AuthResp2> {
     let token_id_res = hex::decode(data.token.clone()); 
     if token_id_res.is_err() {
         println!("token decode error!"); 
@@ -307,7 +320,7 @@ pub async fn process_signup(data: Json<NewUserJson>) -> Json<AuthResp2> {
             });
         }
 
-        let _new_user = User::create(data);
+        let _new_user = User::create(data, i18n::request_locale(&req).await);
 
         let _session_user = SessionUser {
             id:    _new_user.id,
@@ -316,36 +329,23 @@ pub async fn process_signup(data: Json<NewUserJson>) -> Json<AuthResp2> {
 
         println!("Yes!!");
 
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("EMAIL_KEY")
-            .expect("EMAIL_KEY must be set");
-        let sg = sendgrid::SGClient::new(api_key); 
-        let mut x_smtpapi = String::new();
-        x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
-
-        let text = "A new user - <strong>".to_owned() 
-            + &_new_user.first_name.clone() 
-            + &" ".to_string() 
-            + &_new_user.last_name.clone() 
-            + &"</strong> has signed up for BJustcoin. Link to the list of users - ".to_string()
-            + &"https://dashboard.bjustcoin.com/users/".to_string();
-        let mail_info = sendgrid::Mail::new()
-            .add_to(sendgrid::Destination {
-                address: "Beatrice.OBrien@justlaw.com",
-                name: "Beatrice OBrien",
-            })
-            .add_from("no-reply@bjustcoin.com")
-            .add_subject("New user in BJustCoin")
-            .add_html(&text)
-            .add_from_name("BJustcoin Team")
-            .add_header("x-cool".to_string(), "indeed")
-            .add_x_smtpapi(&x_smtpapi);
-
-        match sg.send(mail_info).await {
-            Err(err) => println!("Error: {}", err),
-            Ok(body) => println!("Response: {:?}", body),
-        };
-        println!("mail send!");
+        mailer::notify_admins (
+            "new_user",
+            "new_user",
+            serde_json::json!({
+                "first_name": _new_user.first_name,
+                "last_name":  _new_user.last_name,
+            }),
+        );
+        crate::websocket::publish_to_admins (
+            crate::websocket::EventType::Signup,
+            _new_user.id,
+            serde_json::json!({
+                "first_name": _new_user.first_name,
+                "last_name":  _new_user.last_name,
+                "email":      _new_user.email,
+            }),
+        );
 
         return Json(AuthResp2 {
             id:         _new_user.id,
@@ -465,4 +465,18 @@ pub async fn process_reset(data: Json<NewPasswordJson>) -> Json<AuthResp2> {
                 });      
             },
         }
-}
\ No newline at end of file
+}
+#[derive(Deserialize)]
+pub struct SetLocaleJson {
+    pub locale: String,
+}
+
+/// Stores the language emails and API messages are sent in.
+pub async fn set_locale(req: HttpRequest, data: Json<SetLocaleJson>) -> Result<HttpResponse, Error> {
+    if !crate::utils::is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = crate::utils::get_current_user(&req);
+    _request_user.set_locale(&data.locale)?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,76 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use serde::{Deserialize, Serialize};
+use crate::models::{
+    Campaign,
+    CampaignJson,
+    CampaignRespData,
+    CampaignRecipientRespData,
+};
+use crate::utils::{
+    check_admin,
+};
+use crate::errors::Error;
+
+
+pub fn campaign_routes(config: &mut web::ServiceConfig) {
+    config.route("/get_campaigns/", web::get().to(get_campaigns));
+    config.route("/get_campaign_recipients/", web::get().to(get_campaign_recipients));
+    config.route("/create_campaign/", web::post().to(create_campaign));
+    config.route("/cancel_campaign/", web::post().to(cancel_campaign));
+}
+
+pub async fn get_campaigns(req: HttpRequest) -> Result<Json<CampaignRespData>, Error> {
+    check_admin(&req)?;
+    let page = crate::utils::get_page(&req);
+    Ok(Json(Campaign::get_list(page.into(), Some(20))))
+}
+
+pub async fn get_campaign_recipients(req: HttpRequest) -> Result<Json<CampaignRecipientRespData>, Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub id: i32,
+    }
+    check_admin(&req)?;
+    let params = web::Query::<Params>::from_query(&req.query_string())
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    let page = crate::utils::get_page(&req);
+    let campaign = Campaign::get_item(params.id)?;
+    Ok(Json(campaign.get_recipients_list(page.into(), Some(50))))
+}
+
+pub async fn create_campaign(req: HttpRequest, data: Json<CampaignJson>) -> Result<Json<Campaign>, Error> {
+    let _request_user = check_admin(&req)?;
+    let campaign = Campaign::create(_request_user.id, &data)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "created a campaign: ".to_string() + &campaign.subject,
+            target_id: None,
+        })
+    });
+    Ok(Json(campaign))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CampaignIdJson {
+    pub id: i32,
+}
+
+pub async fn cancel_campaign(req: HttpRequest, data: Json<CampaignIdJson>) -> Result<HttpResponse, Error> {
+    let _request_user = check_admin(&req)?;
+    let campaign = Campaign::get_item(data.id)?;
+    campaign.cancel()?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "cancelled a campaign: ".to_string() + &campaign.subject,
+            target_id: None,
+        })
+    });
+    Ok(HttpResponse::Ok().finish())
+}
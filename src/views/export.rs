@@ -0,0 +1,124 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web,
+    web::Json,
+};
+use serde::Deserialize;
+use crate::models::{
+    Holder,
+    HolderImportReport,
+    SuggestItem,
+    Subscriber,
+    User,
+};
+use crate::utils::{
+    check_admin,
+    csv_stream,
+    xlsx_stream,
+    ExportRow,
+};
+use crate::errors::Error;
+
+
+/// Holder CSVs are far larger than the default 256 KB body limit.
+const UPLOAD_LIMIT: usize = 20 * 1024 * 1024;
+
+pub fn export_routes(config: &mut web::ServiceConfig) {
+    config.service(
+        web::resource("/upload_holders/")
+            .app_data(web::PayloadConfig::new(UPLOAD_LIMIT))
+            .route(web::post().to(upload_holders))
+    );
+    config.route("/export_holders/", web::get().to(export_holders));
+    config.route("/export_applications/", web::get().to(export_applications));
+    config.route("/export_users/", web::get().to(export_users));
+    config.route("/export_subscribers/", web::get().to(export_subscribers));
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    pub format: Option<String>,
+    pub status: Option<i16>,
+}
+
+fn get_export_params(req: &HttpRequest) -> ExportParams {
+    web::Query::<ExportParams>::from_query(&req.query_string())
+        .map(|p| p.into_inner())
+        .unwrap_or(ExportParams {
+            format: None,
+            status: None,
+        })
+}
+
+fn attachment(name: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters:  vec![DispositionParam::Filename(name)],
+    }
+}
+
+async fn export<T, F>(format: Option<String>, name: &'static str, fetch: F) -> Result<HttpResponse, Error>
+where
+    T: ExportRow + Send + 'static,
+    F: Fn(i32, i64) -> Vec<T> + Clone + Send + 'static,
+{
+    match format.as_deref() {
+        Some("xlsx") => {
+            let body = xlsx_stream(name, fetch)
+                .await
+                .map_err(|err| Error::InternalServerError(err.to_string()))?;
+            Ok(HttpResponse::Ok()
+                .content_type("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+                .insert_header(attachment(name.to_string() + ".xlsx"))
+                .streaming(body))
+        },
+        None | Some("csv") => {
+            Ok(HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header(attachment(name.to_string() + ".csv"))
+                .streaming(csv_stream(fetch)))
+        },
+        Some(other) => Err(Error::BadRequest("Unknown export format: ".to_string() + other)),
+    }
+}
+
+pub async fn upload_holders(req: HttpRequest, body: web::Bytes) -> Result<Json<HolderImportReport>, Error> {
+    let _request_user = check_admin(&req)?;
+    let report = web::block(move || Holder::import_csv(&body)).await?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "uploaded holders from csv".to_string(),
+            target_id: None,
+        })
+    });
+    Ok(Json(report))
+}
+
+pub async fn export_holders(req: HttpRequest) -> Result<HttpResponse, Error> {
+    check_admin(&req)?;
+    export(get_export_params(&req).format, "holders", Holder::get_after).await
+}
+
+pub async fn export_applications(req: HttpRequest) -> Result<HttpResponse, Error> {
+    check_admin(&req)?;
+    let params = get_export_params(&req);
+    let (status, name) = match params.status {
+        Some(1) => (1, "approved_applications"),
+        Some(2) => (2, "rejected_applications"),
+        _ =>       (0, "new_applications"),
+    };
+    export(params.format, name, move |after_id, limit| SuggestItem::get_after(status, after_id, limit)).await
+}
+
+pub async fn export_users(req: HttpRequest) -> Result<HttpResponse, Error> {
+    check_admin(&req)?;
+    export(get_export_params(&req).format, "users", User::get_after).await
+}
+
+pub async fn export_subscribers(req: HttpRequest) -> Result<HttpResponse, Error> {
+    check_admin(&req)?;
+    export(get_export_params(&req).format, "subscribers", Subscriber::get_after).await
+}
@@ -0,0 +1,248 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use serde::{Deserialize, Serialize};
+use crate::utils::check_admin;
+use crate::mailer::{self, RenderedMail};
+use crate::models::{
+    EmailOutbox,
+    EmailOutboxRespData,
+    NotificationSubscription,
+    NotificationSubscriptionJson,
+    User,
+    NOTIFY_EVENTS,
+    NOTIFY_CHANNELS,
+    EmailEvent,
+    EmailEventRespData,
+    EmailSuppression,
+    EmailSuppressionRespData,
+    SendGridEvent,
+};
+use crate::errors::Error;
+
+
+pub fn mail_routes(config: &mut web::ServiceConfig) {
+    config.route("/get_email_templates/", web::get().to(get_email_templates));
+    config.route("/preview_email/", web::post().to(preview_email));
+    config.route("/get_email_outbox/", web::get().to(get_email_outbox));
+    config.route("/retry_email/", web::post().to(retry_email));
+    config.route("/get_notification_settings/", web::get().to(get_notification_settings));
+    config.route("/set_notification_settings/", web::post().to(set_notification_settings));
+    config.route("/get_notification_emails/", web::get().to(get_notification_emails));
+    config.route("/set_notification_emails/", web::post().to(set_notification_emails));
+    config.route("/sendgrid_events/", web::post().to(sendgrid_events));
+    config.route("/get_email_events/", web::get().to(get_email_events));
+    config.route("/get_email_suppressions/", web::get().to(get_email_suppressions));
+    config.route("/delete_email_suppression/", web::post().to(delete_email_suppression));
+}
+
+pub async fn get_email_templates(req: HttpRequest) -> Result<Json<Vec<&'static str>>, Error> {
+    let _request_user = check_admin(&req)?;
+    Ok(Json(mailer::template_names()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewEmailJson {
+    pub template: String,
+    pub locale:   Option<String>,
+    #[serde(default)]
+    pub context:  serde_json::Map<String, serde_json::Value>,
+}
+
+pub async fn preview_email(req: HttpRequest, data: Json<PreviewEmailJson>) -> Result<Json<RenderedMail>, Error> {
+    let _request_user = check_admin(&req)?;
+    let locale = data.locale.clone().unwrap_or_else(|| crate::i18n::DEFAULT_LOCALE.to_string());
+    Ok(Json(mailer::render_localized(&data.template, &locale, &data.context)?))
+}
+
+pub async fn get_email_outbox(req: HttpRequest) -> Result<Json<EmailOutboxRespData>, Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub status: Option<i16>,
+    }
+    let _request_user = check_admin(&req)?;
+    let page = crate::utils::get_page(&req);
+    let status = web::Query::<Params>::from_query(&req.query_string())
+        .ok()
+        .and_then(|p| p.status);
+    Ok(Json(EmailOutbox::get_list(status, page.into(), Some(20))))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EmailIdJson {
+    pub id: i32,
+}
+
+pub async fn retry_email(req: HttpRequest, data: Json<EmailIdJson>) -> Result<HttpResponse, Error> {
+    let _request_user = check_admin(&req)?;
+    EmailOutbox::retry(data.id)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationSettingsResp {
+    pub user_id:  i32,
+    pub events:   Vec<&'static str>,
+    pub channels: Vec<&'static str>,
+    pub items:    Vec<NotificationSubscription>,
+}
+
+/// Admins manage their own notifications; a superuser may pass `user_id`
+/// to manage another admin's.
+fn get_settings_user(request_user: &User, user_id: Option<i32>) -> Result<i32, Error> {
+    match user_id {
+        Some(id) if id != request_user.id => {
+            if !request_user.is_superuser() {
+                return Err(Error::Forbidden);
+            }
+            let _user = User::get_user(id)?;
+            if !_user.is_admin() {
+                return Err(Error::BadRequest("Notifications are only sent to admins".to_string()));
+            }
+            Ok(id)
+        },
+        _ => Ok(request_user.id),
+    }
+}
+
+pub async fn get_notification_settings(req: HttpRequest) -> Result<Json<NotificationSettingsResp>, Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub user_id: Option<i32>,
+    }
+    let _request_user = check_admin(&req)?;
+    let params = web::Query::<Params>::from_query(&req.query_string())
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    let user_id = get_settings_user(&_request_user, params.user_id)?;
+    Ok(Json(NotificationSettingsResp {
+        user_id:  user_id,
+        events:   NOTIFY_EVENTS.to_vec(),
+        channels: NOTIFY_CHANNELS.to_vec(),
+        items:    NotificationSubscription::get_for_user(user_id),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationSettingsJson {
+    pub user_id: Option<i32>,
+    pub items:   Vec<NotificationSubscriptionJson>,
+}
+
+pub async fn set_notification_settings(req: HttpRequest, data: Json<NotificationSettingsJson>) -> Result<Json<NotificationSettingsResp>, Error> {
+    let _request_user = check_admin(&req)?;
+    let user_id = get_settings_user(&_request_user, data.user_id)?;
+    let items = NotificationSubscription::set_for_user(user_id, &data.items)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "changed notification settings".to_string(),
+            target_id: Some(user_id),
+        })
+    });
+    Ok(Json(NotificationSettingsResp {
+        user_id:  user_id,
+        events:   NOTIFY_EVENTS.to_vec(),
+        channels: NOTIFY_CHANNELS.to_vec(),
+        items:    items,
+    }))
+}
+
+/// Outside addresses that get admin notifications, e.g. an external
+/// monitor. Only superusers manage them.
+pub async fn get_notification_emails(req: HttpRequest) -> Result<Json<Vec<NotificationSubscription>>, Error> {
+    let _request_user = check_admin(&req)?;
+    if !_request_user.is_superuser() {
+        return Err(Error::Forbidden);
+    }
+    Ok(Json(NotificationSubscription::get_external()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationEmailJson {
+    pub email: String,
+    pub name:  String,
+    pub items: Vec<NotificationSubscriptionJson>,
+}
+
+pub async fn set_notification_emails(req: HttpRequest, data: Json<NotificationEmailJson>) -> Result<Json<Vec<NotificationSubscription>>, Error> {
+    let _request_user = check_admin(&req)?;
+    if !_request_user.is_superuser() {
+        return Err(Error::Forbidden);
+    }
+    let items = NotificationSubscription::set_for_email(&data.email, &data.name, &data.items)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "changed notifications of an outside email".to_string(),
+            target_id: None,
+        })
+    });
+    Ok(Json(items))
+}
+
+/// How far, in seconds, a webhook timestamp may be from our clock.
+const SENDGRID_MAX_SKEW: i64 = 5 * 60;
+
+/// SendGrid event webhook. Only batches signed with the key configured in
+/// `SENDGRID_WEBHOOK_KEY` are accepted.
+pub async fn sendgrid_events(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, Error> {
+    let public_key = crate::vars::sendgrid_webhook_key()
+        .ok_or_else(|| Error::InternalServerError("SENDGRID_WEBHOOK_KEY is not set".to_string()))?;
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+            .ok_or(Error::Unauthorized)
+    };
+    let signature = header("X-Twilio-Email-Event-Webhook-Signature")?;
+    let timestamp = header("X-Twilio-Email-Event-Webhook-Timestamp")?;
+    if !crate::utils::verify_sendgrid_signature(&public_key, &signature, &timestamp, &body) {
+        return Err(Error::Unauthorized);
+    }
+    // the signature covers the timestamp, so a fresh one rules out replays
+    // of old captured requests
+    let sent = timestamp.trim().parse::<i64>().map_err(|_| Error::Unauthorized)?;
+    if (chrono::Utc::now().timestamp() - sent).abs() > SENDGRID_MAX_SKEW {
+        return Err(Error::Unauthorized);
+    }
+    let events = serde_json::from_slice::<Vec<SendGridEvent>>(&body)
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    web::block(move || EmailEvent::create(events)).await??;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_email_events(req: HttpRequest) -> Result<Json<EmailEventRespData>, Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub email: Option<String>,
+    }
+    let _request_user = check_admin(&req)?;
+    let page = crate::utils::get_page(&req);
+    let email = web::Query::<Params>::from_query(&req.query_string())
+        .ok()
+        .and_then(|p| p.into_inner().email);
+    Ok(Json(EmailEvent::get_list(email, page.into(), Some(20))))
+}
+
+pub async fn get_email_suppressions(req: HttpRequest) -> Result<Json<EmailSuppressionRespData>, Error> {
+    let _request_user = check_admin(&req)?;
+    let page = crate::utils::get_page(&req);
+    Ok(Json(EmailSuppression::get_list(page.into(), Some(20))))
+}
+
+pub async fn delete_email_suppression(req: HttpRequest, data: Json<EmailIdJson>) -> Result<HttpResponse, Error> {
+    let _request_user = check_admin(&req)?;
+    EmailSuppression::delete(data.id)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "removed an email suppression".to_string(),
+            target_id: None,
+        })
+    });
+    Ok(HttpResponse::Ok().finish())
+}
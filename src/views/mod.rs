@@ -1,7 +1,19 @@
 pub mod auth;
 pub mod admin_progs;
+pub mod payments;
+pub mod export;
+pub mod wallets;
+pub mod mail;
+pub mod campaigns;
+pub mod notifications;
 
 pub use self::{
     auth::*,
     admin_progs::*,
+    payments::*,
+    export::*,
+    wallets::*,
+    mail::*,
+    campaigns::*,
+    notifications::*,
 };
@@ -0,0 +1,70 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use serde::{Deserialize, Serialize};
+use crate::models::{
+    Notification,
+    NotificationRespData,
+};
+use crate::utils::{
+    is_signed_in,
+    get_current_user,
+};
+use crate::errors::Error;
+
+
+pub fn notification_routes(config: &mut web::ServiceConfig) {
+    config.route("/get_notifications/", web::get().to(get_notifications));
+    config.route("/get_unread_notifications_count/", web::get().to(get_unread_notifications_count));
+    config.route("/read_notifications/", web::post().to(read_notifications));
+}
+
+pub async fn get_notifications(req: HttpRequest) -> Result<Json<NotificationRespData>, Error> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub unread: Option<bool>,
+    }
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let page = crate::utils::get_page(&req);
+    let unread = web::Query::<Params>::from_query(&req.query_string())
+        .ok()
+        .and_then(|p| p.unread)
+        .unwrap_or(false);
+    Ok(Json(Notification::get_list(_request_user.id, unread, page.into(), Some(20))))
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCountResp {
+    pub count: i64,
+}
+
+pub async fn get_unread_notifications_count(req: HttpRequest) -> Result<Json<UnreadCountResp>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    Ok(Json(UnreadCountResp {
+        count: Notification::count_unread(_request_user.id),
+    }))
+}
+
+/// `ids` are marked read; without `ids` every notification is.
+#[derive(Debug, Deserialize)]
+pub struct ReadNotificationsJson {
+    pub ids: Option<Vec<i32>>,
+}
+
+pub async fn read_notifications(req: HttpRequest, data: Json<ReadNotificationsJson>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    Notification::mark_read(_request_user.id, data.into_inner().ids)?;
+    Ok(HttpResponse::Ok().finish())
+}
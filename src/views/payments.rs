@@ -0,0 +1,78 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use crate::models::{
+    Payment,
+    PaymentRespData,
+    PaymentTxJson,
+};
+use serde::{Deserialize, Serialize};
+use crate::utils::{
+    is_signed_in,
+    get_current_user,
+};
+use crate::errors::Error;
+
+
+pub fn payment_routes(config: &mut web::ServiceConfig) {
+    config.route("/get_payments/", web::get().to(get_payments));
+    config.route("/get_user_payments/", web::get().to(get_user_payments));
+    config.route("/record_payment/", web::post().to(record_payment));
+    config.route("/confirm_payment/", web::post().to(confirm_payment));
+}
+
+pub async fn get_payments(req: HttpRequest) -> Json<PaymentRespData> {
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        pub status: Option<i16>,
+    }
+    if is_signed_in(&req) {
+        let _request_user = get_current_user(&req);
+        if _request_user.is_admin() {
+            let page = crate::utils::get_page(&req);
+            let status = web::Query::<Params>::from_query(&req.query_string())
+                .ok()
+                .and_then(|p| p.status);
+            return Json(Payment::get_list(status, page.into(), Some(20)));
+        }
+    }
+    Json(PaymentRespData {
+        data: Vec::new(),
+        next: 0,
+    })
+}
+
+pub async fn get_user_payments(req: HttpRequest) -> Json<Vec<Payment>> {
+    if is_signed_in(&req) {
+        let _request_user = get_current_user(&req);
+        return Json(Payment::get_for_user(_request_user.id));
+    }
+    Json(Vec::new())
+}
+
+pub async fn record_payment(req: HttpRequest, data: Json<PaymentTxJson>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let payment = Payment::get_item(data.id)?;
+    payment.record_tx(&_request_user, data.tx_hash.clone())?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PaymentId {
+    pub id: i32,
+}
+pub async fn confirm_payment(req: HttpRequest, data: Json<PaymentId>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let payment = Payment::get_item(data.id)?;
+    payment.confirm(&_request_user)?;
+    Ok(HttpResponse::Ok().finish())
+}
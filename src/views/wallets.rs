@@ -0,0 +1,211 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use serde::{Deserialize, Serialize};
+use crate::models::{
+    NewWallet,
+    NewWhiteList,
+    WalletChallenge,
+    WalletChallengeJson,
+    WalletChallengeResp,
+    WalletSignatureJson,
+    WhiteListTree,
+    WhiteListRootResp,
+    WhiteListProofResp,
+    WhiteListMismatch,
+};
+use crate::utils::{
+    is_signed_in,
+    get_current_user,
+};
+use crate::errors::Error;
+
+
+pub fn wallet_routes(config: &mut web::ServiceConfig) {
+    config.route("/wallet_challenge/", web::post().to(wallet_challenge));
+    config.route("/verify_wallet/", web::post().to(verify_wallet));
+    config.route("/get_wallets/", web::get().to(get_wallets));
+    config.route("/add_wallet/", web::post().to(add_wallet));
+    config.route("/delete_wallet/", web::post().to(delete_wallet));
+
+    config.route("/get_white_list/", web::get().to(get_white_list));
+    config.route("/add_white_list/", web::post().to(add_white_list));
+    config.route("/delete_white_list/", web::post().to(delete_white_list));
+
+    config.route("/publish_white_list_root/", web::post().to(publish_white_list_root));
+    config.route("/get_white_list_root/", web::get().to(get_white_list_root));
+    config.route("/get_white_list_proof/", web::get().to(get_white_list_proof));
+    config.route("/get_white_list_mismatches/", web::get().to(get_white_list_mismatches));
+}
+
+pub async fn wallet_challenge(req: HttpRequest, data: Json<WalletChallengeJson>) -> Result<Json<WalletChallengeResp>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let challenge = WalletChallenge::create(_request_user.id, &data.address)?;
+    Ok(Json(challenge.get_resp()))
+}
+
+pub async fn verify_wallet(req: HttpRequest, data: Json<WalletSignatureJson>) -> Result<Json<NewWallet>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let wallet = WalletChallenge::verify(_request_user.id, &data.address, &data.signature)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "verified a wallet".to_string(),
+            target_id: None,
+        })
+    });
+    Ok(Json(wallet))
+}
+
+pub async fn get_wallets(req: HttpRequest) -> Json<Vec<NewWallet>> {
+    if is_signed_in(&req) {
+        let _request_user = get_current_user(&req);
+        return Json(NewWallet::get_for_user(_request_user.id));
+    }
+    Json(Vec::new())
+}
+
+pub async fn add_wallet(req: HttpRequest, data: Json<WalletChallengeJson>) -> Result<Json<NewWallet>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let wallet = NewWallet::create(_request_user.id, &data.address)?;
+    Ok(Json(wallet))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WalletId {
+    pub id: i32,
+}
+pub async fn delete_wallet(req: HttpRequest, data: Json<WalletId>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let wallet = NewWallet::get_item(data.id)?;
+    if wallet.user_id != _request_user.id && !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    NewWallet::delete(wallet.id);
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_white_list(req: HttpRequest) -> Result<Json<Vec<NewWhiteList>>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    let id = crate::utils::get_id(&req);
+    if id != _request_user.id && !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    Ok(Json(NewWhiteList::get_for_user(id)))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WhiteListJson {
+    pub user_id:    i32,
+    pub token_type: i16,
+    pub allocation: Option<String>,
+}
+pub async fn add_white_list(req: HttpRequest, data: Json<WhiteListJson>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    let allocation = data.allocation.clone().unwrap_or_else(|| "0".to_string());
+    if crate::utils::parse_units(&allocation, crate::vars::token_decimals()).is_none() {
+        return Err(Error::BadRequest("allocation is not a valid amount: ".to_string() + &allocation));
+    }
+    NewWhiteList::create(data.user_id, data.token_type, allocation)?;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "added to the white list: ".to_string() + &crate::models::User::get_tokenomic_type(data.token_type),
+            target_id: Some(data.user_id),
+        })
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn delete_white_list(req: HttpRequest, data: Json<WhiteListJson>) -> Result<HttpResponse, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    NewWhiteList::delete(data.user_id, data.token_type);
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "removed from the white list: ".to_string() + &crate::models::User::get_tokenomic_type(data.token_type),
+            target_id: Some(data.user_id),
+        })
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StageJson {
+    pub token_type: i16,
+}
+pub async fn publish_white_list_root(req: HttpRequest, data: Json<StageJson>) -> Result<Json<WhiteListRootResp>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    let token_type = data.token_type;
+    let resp = web::block(move || WhiteListTree::publish(token_type)).await??;
+    crate::models::Log::create({
+        Json(crate::models::NewLogJson {
+            user_id:   _request_user.id,
+            text:      "published the white list root: ".to_string() + &crate::models::User::get_tokenomic_type(token_type),
+            target_id: None,
+        })
+    });
+    Ok(Json(resp))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WhiteListProofParams {
+    pub token_type: i16,
+    pub address:    Option<crate::utils::WalletAddress>,
+}
+pub async fn get_white_list_root(params: web::Query<WhiteListProofParams>) -> Result<Json<WhiteListRootResp>, Error> {
+    let tree = WhiteListTree::get_latest(params.token_type)?;
+    Ok(Json(tree.get_root()))
+}
+pub async fn get_white_list_proof(params: web::Query<WhiteListProofParams>) -> Result<Json<WhiteListProofResp>, Error> {
+    let address = params.address.clone()
+        .ok_or_else(|| Error::BadRequest("address is required".to_string()))?;
+    let tree = WhiteListTree::get_latest(params.token_type)?;
+    Ok(Json(tree.get_proof(&address)?))
+}
+
+pub async fn get_white_list_mismatches(req: HttpRequest) -> Result<Json<Vec<WhiteListMismatch>>, Error> {
+    if !is_signed_in(&req) {
+        return Err(Error::Unauthorized);
+    }
+    let _request_user = get_current_user(&req);
+    if !_request_user.is_admin() {
+        return Err(Error::Forbidden);
+    }
+    Ok(Json(web::block(|| WhiteListMismatch::get_stored()).await?))
+}
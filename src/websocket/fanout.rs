@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use actix::prelude::Addr;
+use actix_web::rt;
+use futures::{stream, StreamExt};
+use postgres_native_tls::MakeTlsConnector;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio_postgres::AsyncMessage;
+
+use crate::websocket::{Relayed, Server};
+
+/// Postgres refuses NOTIFY payloads of 8000 bytes or more.
+const MAX_PAYLOAD: usize = 7999;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Messages waiting to be published or delivered; past this the
+/// publishers fall back to local delivery rather than queue without bound.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Relays websocket messages between backend instances through Postgres
+/// `LISTEN/NOTIFY`. Every message published on any instance, this one
+/// included, arrives on the channel and is delivered to the local `Server`,
+/// so an instance never delivers its own messages directly. Lost
+/// connections are re-established with backoff; messages published while
+/// the connection is down are delivered on this instance only.
+pub async fn relay(server: Addr<Server>, mut outgoing: Receiver<String>) {
+    let channel = crate::vars::ws_channel();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match relay_once(&server, &channel, &mut outgoing).await {
+            Ok(()) => return,
+            Err(err) => println!("websocket fan-out: {}, reconnecting in {:?}", err, backoff),
+        }
+        // whatever was published in the meantime only reaches local clients
+        while let Ok(payload) = outgoing.try_recv() {
+            deliver(&server, &payload);
+        }
+        rt::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Runs one connection until it fails. Ok means the app is shutting down.
+/// `sslmode` of `DATABASE_URL` decides whether TLS is used, as it does
+/// for the diesel connections.
+async fn relay_once(server: &Addr<Server>, channel: &str, outgoing: &mut Receiver<String>) -> Result<(), String> {
+    let connector = native_tls::TlsConnector::new()
+        .map_err(|e| "TLS connector: ".to_string() + &e.to_string())?;
+    let (client, mut connection) = tokio_postgres::connect(&crate::vars::database_url(), MakeTlsConnector::new(connector))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // the connection has to be polled for queries to complete, so it is
+    // driven by its own task that hands notifications over
+    let (incoming_tx, mut incoming) = mpsc::channel::<String>(QUEUE_CAPACITY);
+    rt::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(n)) => {
+                    if incoming_tx.send(n.payload().to_string()).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    println!("websocket fan-out connection: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    client
+        .batch_execute(&format!("LISTEN \"{}\"", channel.replace('"', "\"\"")))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    loop {
+        tokio::select! {
+            payload = incoming.recv() => match payload {
+                Some(payload) => deliver(server, &payload),
+                None => return Err("connection closed".to_string()),
+            },
+            payload = outgoing.recv() => match payload {
+                Some(payload) if payload.len() > MAX_PAYLOAD => {
+                    println!("websocket fan-out: {} byte message is too large, delivered locally", payload.len());
+                    deliver(server, &payload);
+                }
+                Some(payload) => {
+                    if let Err(err) = client.execute("SELECT pg_notify($1, $2)", &[&channel, &payload]).await {
+                        deliver(server, &payload);
+                        return Err(err.to_string());
+                    }
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+fn deliver(server: &Addr<Server>, payload: &str) {
+    match serde_json::from_str::<Relayed>(payload) {
+        Ok(Relayed::Deliver(message)) => server.do_send(message),
+        Ok(Relayed::DisconnectUser(message)) => server.do_send(message),
+        Err(err) => println!("websocket fan-out: bad message: {}", err),
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 
 use uuid::Uuid;
@@ -9,34 +11,177 @@ use actix::{
 };
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{channel, error::TrySendError, Sender};
 
 use crate::errors::Error;
+use crate::models::{User, WsTicket};
 
+mod fanout;
+mod protocol;
 mod server;
-pub use self::server::*;
+pub use self::{
+    protocol::*,
+    server::*,
+};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
 
+pub fn websocket_routes(config: &mut web::ServiceConfig) {
+    config.route("/ws/", web::get().to(ws_index));
+    config.route("/ws_ticket/", web::post().to(ws_ticket));
+}
+
+static SERVER: OnceLock<Addr<Server>> = OnceLock::new();
+static FANOUT: OnceLock<Sender<String>> = OnceLock::new();
+
+/// Starts the `Server` actor and its `LISTEN/NOTIFY` relay; must be called
+/// inside the actix system.
+pub fn start() -> Addr<Server> {
+    SERVER.get_or_init(|| {
+        let server = Server::new().start();
+        let (sender, receiver) = channel(fanout::QUEUE_CAPACITY);
+        let _ = FANOUT.set(sender);
+        actix_web::rt::spawn(fanout::relay(server.clone(), receiver));
+        server
+    }).clone()
+}
+
+/// Hands `message` to every backend instance. Callable from anywhere,
+/// including blocking threads. Does nothing before `start`.
+fn relay(message: Relayed) {
+    let payload = match serde_json::to_string(&message) {
+        Ok(payload) => payload,
+        Err(err) => {
+            println!("websocket message error: {}", err);
+            return;
+        }
+    };
+    if let Some(fanout) = FANOUT.get() {
+        if let Err(err) = fanout.try_send(payload) {
+            if let TrySendError::Full(_) = err {
+                println!("websocket fan-out: queue is full, delivered locally");
+            }
+            // the relay is gone or behind; at least reach clients of this instance
+            if let Some(server) = SERVER.get() {
+                match message {
+                    Relayed::Deliver(message) => server.do_send(message),
+                    Relayed::DisconnectUser(message) => server.do_send(message),
+                }
+            }
+        }
+    }
+}
+
+/// Delivers `message` to `audience` on every backend instance.
+pub fn publish(audience: Audience, message: MessageToClient) {
+    relay(Relayed::Deliver(Deliver { audience, message }));
+}
+
+/// Closes the user's websocket sessions on every backend instance, so a
+/// blocked or demoted user stops receiving events at once.
+pub fn disconnect_user(user_id: i32) {
+    relay(Relayed::DisconnectUser(DisconnectUser { user_id }));
+}
+
+/// Publishes a dashboard event to connected admins.
+pub fn publish_to_admins(types: EventType, id: i32, data: serde_json::Value) {
+    publish(Audience::Admins, MessageToClient::new(types, id, data));
+}
+
+/// Publishes an event to the sessions subscribed to `topic`.
+pub fn publish_to_topic(topic: Topic, types: EventType, id: i32, data: serde_json::Value) {
+    publish(Audience::Topic(topic.name()), MessageToClient::new(types, id, data));
+}
+
 pub struct WebSocketSession {
     id: String,
     hb: Instant,
+    user_id: i32,
+    is_admin: bool,
+    topics: HashSet<String>,
     server_addr: Addr<Server>,
 }
 
 impl WebSocketSession {
-    fn new(server_addr: Addr<Server>) -> Self {
+    fn new(user: &User, server_addr: Addr<Server>) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             hb: Instant::now(),
+            user_id: user.id,
+            is_admin: user.is_admin(),
+            topics: HashSet::new(),
             server_addr,
         }
     }
 
+    fn send_frame(&self, ctx: &mut <Self as Actor>::Context, frame: &ServerFrame) {
+        match serde_json::to_string(frame) {
+            Ok(text) => ctx.text(text),
+            Err(err) => println!("websocket frame error: {}", err),
+        }
+    }
+
+    fn handle_client_message(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+        let msg = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                self.send_frame(ctx, &ServerFrame::error(None, "bad_request", &err.to_string()));
+                return;
+            }
+        };
+        match msg {
+            ClientMessage::Subscribe { request_id, topic } => {
+                let topic = match Topic::parse(&topic) {
+                    Ok(topic) => topic,
+                    Err(err) => {
+                        self.send_frame(ctx, &ServerFrame::error(request_id, "unknown_topic", &err));
+                        return;
+                    }
+                };
+                if self.topics.len() >= MAX_TOPICS && !self.topics.contains(&topic.name()) {
+                    self.send_frame(ctx, &ServerFrame::error(request_id, "too_many_topics", "Too many topics"));
+                    return;
+                }
+                let (user_id, is_admin) = (self.user_id, self.is_admin);
+                let check = topic.clone();
+                web::block(move || check.is_allowed(user_id, is_admin))
+                    .into_actor(self)
+                    .then(move |res, act, ctx| {
+                        match res {
+                            Ok(true) => {
+                                act.topics.insert(topic.name());
+                                act.server_addr.do_send(Subscribe {
+                                    id: act.id.clone(),
+                                    topic: topic.name(),
+                                });
+                                act.send_frame(ctx, &ServerFrame::Ack { request_id, topic: Some(topic.name()) });
+                            }
+                            Ok(false) => act.send_frame(ctx, &ServerFrame::error(request_id, "forbidden", "Forbidden")),
+                            Err(_) => act.send_frame(ctx, &ServerFrame::error(request_id, "internal", "Internal Server Error")),
+                        }
+                        fut::ready(())
+                    })
+                    .spawn(ctx);
+            }
+            ClientMessage::Unsubscribe { request_id, topic } => {
+                self.topics.remove(&topic);
+                self.server_addr.do_send(Unsubscribe {
+                    id: self.id.clone(),
+                    topic: topic.clone(),
+                });
+                self.send_frame(ctx, &ServerFrame::Ack { request_id, topic: Some(topic) });
+            }
+            ClientMessage::Ping { request_id } => {
+                self.send_frame(ctx, &ServerFrame::Pong { request_id });
+            }
+        }
+    }
+
     fn send_heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
         ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
             if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                act.server_addr.do_send(Disconnect { id: act.id.clone() });
                 ctx.stop();
                 return;
             }
@@ -50,12 +195,19 @@ impl Actor for WebSocketSession {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         self.send_heartbeat(ctx);
+        self.send_frame(ctx, &ServerFrame::Welcome {
+            version: PROTOCOL_VERSION,
+            session: &self.id,
+        });
 
         let session_addr = ctx.address();
         self.server_addr
             .send(Connect {
-                addr: session_addr.recipient(),
+                addr: session_addr.clone().recipient(),
+                close: session_addr.recipient(),
                 id: self.id.clone(),
+                user_id: self.user_id,
+                is_admin: self.is_admin,
             })
             .into_actor(self)
             .then(|res, _act, ctx| {
@@ -67,6 +219,10 @@ impl Actor for WebSocketSession {
             })
             .wait(ctx);
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.server_addr.do_send(Disconnect { id: self.id.clone() });
+    }
 }
 
 impl Handler<Message> for WebSocketSession {
@@ -77,6 +233,15 @@ impl Handler<Message> for WebSocketSession {
     }
 }
 
+impl Handler<Close> for WebSocketSession {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseCode::Policy.into()));
+        ctx.stop();
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
@@ -87,11 +252,14 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            Ok(ws::Message::Text(text)) => {
+                self.hb = Instant::now();
+                self.handle_client_message(&text, ctx);
+            }
+            Ok(ws::Message::Binary(_)) => {
+                self.send_frame(ctx, &ServerFrame::error(None, "bad_request", "Binary frames are not supported"));
+            }
             Ok(ws::Message::Close(reason)) => {
-                self.server_addr.do_send(Disconnect {
-                    id: self.id.clone(),
-                });
                 ctx.close(reason);
                 ctx.stop();
             }
@@ -103,13 +271,79 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
     }
 }
 
+#[derive(Deserialize)]
+struct WsParams {
+    ticket:  Option<String>,
+    version: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct WsTicketResp {
+    pub ticket:  String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+/// Browsers cannot set headers on a websocket handshake, so they fetch a
+/// single-use ticket with their `secret` and pass it as `?ticket=`.
+pub async fn ws_ticket(req: HttpRequest) -> Result<web::Json<WsTicketResp>, Error> {
+    let secret = req.headers()
+        .get("secret")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .ok_or(Error::Unauthorized)?;
+    let ticket = web::block(move || {
+        let user = User::get_user_with_uuid(&secret).map_err(|_| Error::Unauthorized)?;
+        if user.is_user_in_block() || user.is_admin_in_block() {
+            return Err(Error::Forbidden);
+        }
+        WsTicket::create(user.id)
+    }).await??;
+    Ok(web::Json(WsTicketResp {
+        ticket:  ticket.ticket,
+        expires: ticket.expires,
+    }))
+}
+
+/// Blocking: the user of the `secret` header, otherwise of the ticket,
+/// which is spent.
+fn get_user(secret: Option<String>, ticket: Option<String>) -> Result<User, Error> {
+    if let Some(secret) = secret {
+        return User::get_user_with_uuid(&secret).map_err(|_| Error::Unauthorized);
+    }
+    let user_id = ticket
+        .and_then(|ticket| WsTicket::consume(&ticket))
+        .ok_or(Error::Unauthorized)?;
+    User::get_user(user_id).map_err(|_| Error::Unauthorized)
+}
+
+/// The protocol version the client asked for, `PROTOCOL_VERSION` if none.
+fn get_version(req: &HttpRequest) -> Result<u32, Error> {
+    let version = web::Query::<WsParams>::from_query(req.query_string())
+        .map_err(|e| Error::BadRequest(e.to_string()))?
+        .version
+        .unwrap_or(PROTOCOL_VERSION);
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(Error::BadRequest("Unsupported protocol version: ".to_string() + &version.to_string()));
+    }
+    Ok(version)
+}
+
 pub async fn ws_index (
     req: HttpRequest,
     stream: web::Payload,
     server_addr: web::Data<Addr<Server>>,
 ) -> Result<HttpResponse, Error> {
+    get_version(&req)?;
+    let secret = req.headers().get("secret").and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    let ticket = web::Query::<WsParams>::from_query(req.query_string())
+        .ok()
+        .and_then(|params| params.into_inner().ticket);
+    let user = web::block(move || get_user(secret, ticket)).await??;
+    if user.is_user_in_block() || user.is_admin_in_block() {
+        return Err(Error::Forbidden);
+    }
     let res = ws::start(
-        WebSocketSession::new(server_addr.get_ref().clone()),
+        WebSocketSession::new(&user, server_addr.get_ref().clone()),
         &req,
         stream,
     )?;
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+use crate::websocket::MessageToClient;
+
+/// Bumped on incompatible changes to the frames below. Clients pass the
+/// version they speak as `?version=` on the handshake.
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const SUPPORTED_VERSIONS: &[u32] = &[1];
+
+/// How many topics one connection may follow at once.
+pub const MAX_TOPICS: usize = 50;
+
+/*
+topics
+
+application:{id} - updates of one application; its applicant and admins
+holders          - changes of the holders list; admins only
+stage:{id}       - totals of an ICO stage, no personal data; any signed-in user
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Topic {
+    Application(i32),
+    Holders,
+    Stage(i16),
+}
+
+impl Topic {
+    pub fn parse(topic: &str) -> Result<Topic, String> {
+        let (kind, id) = match topic.split_once(':') {
+            Some((kind, id)) => (kind, Some(id)),
+            None => (topic, None),
+        };
+        match (kind, id) {
+            ("application", Some(id)) => id.parse::<i32>()
+                .map(Topic::Application)
+                .map_err(|_| "Invalid application id: ".to_string() + id),
+            ("stage", Some(id)) => id.parse::<i16>()
+                .map(Topic::Stage)
+                .map_err(|_| "Invalid stage: ".to_string() + id),
+            ("holders", None) => Ok(Topic::Holders),
+            _ => Err("Unknown topic: ".to_string() + topic),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Topic::Application(id) => format!("application:{}", id),
+            Topic::Holders => "holders".to_string(),
+            Topic::Stage(id) => format!("stage:{}", id),
+        }
+    }
+
+    /// Blocking: looks the application up for `Topic::Application`.
+    pub fn is_allowed(&self, user_id: i32, is_admin: bool) -> bool {
+        match self {
+            Topic::Application(id) => is_admin || crate::models::SuggestItem::is_owner(*id, user_id),
+            Topic::Holders => is_admin,
+            Topic::Stage(_) => true,
+        }
+    }
+}
+
+/// Frames sent by clients. `request_id` is echoed in the matching `ack` or
+/// `error` frame.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        request_id: Option<u64>,
+        topic:      String,
+    },
+    Unsubscribe {
+        request_id: Option<u64>,
+        topic:      String,
+    },
+    Ping {
+        request_id: Option<u64>,
+    },
+}
+
+/// Frames sent to clients.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame<'a> {
+    Welcome {
+        version: u32,
+        session: &'a str,
+    },
+    Ack {
+        request_id: Option<u64>,
+        topic:      Option<String>,
+    },
+    Pong {
+        request_id: Option<u64>,
+    },
+    Error {
+        request_id: Option<u64>,
+        code:       &'static str,
+        message:    String,
+    },
+    /// `topic` is None for events sent to a user or role rather than to
+    /// the followers of a topic.
+    Event {
+        topic: Option<&'a str>,
+        #[serde(flatten)]
+        event: &'a MessageToClient,
+    },
+}
+
+impl<'a> ServerFrame<'a> {
+    pub fn error(request_id: Option<u64>, code: &'static str, message: &str) -> ServerFrame<'a> {
+        ServerFrame::Error {
+            request_id: request_id,
+            code:       code,
+            message:    message.to_string(),
+        }
+    }
+}
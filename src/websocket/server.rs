@@ -1,53 +1,139 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use actix::prelude::{Actor, Context, Handler, Message as ActixMessage, Recipient};
 use serde::{Deserialize, Serialize};
-use serde_json::{error::Result as SerdeResult, to_string, Value};
+use serde_json::{to_string, Value};
+
+use crate::websocket::ServerFrame;
 
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct Message(pub String);
 
+/// Tells a session to close its connection.
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Close;
+
+/// What a `MessageToClient` is about; `id` is the id of the record of
+/// that kind.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    /// a user signed up
+    Signup,
+    /// an application for tokens was submitted
+    Application,
+    /// an application was approved
+    Approval,
+    /// an application was rejected
+    Rejection,
+    /// a payment for an application was confirmed
+    PaymentConfirmed,
+    /// a user or an admin was blocked
+    Block,
+    /// a new entry in the logs
+    Log,
+    /// the holders list changed
+    Holders,
+}
+
+impl EventType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Signup =>           "signup",
+            EventType::Application =>      "application",
+            EventType::Approval =>         "approval",
+            EventType::Rejection =>        "rejection",
+            EventType::PaymentConfirmed => "payment_confirmed",
+            EventType::Block =>            "block",
+            EventType::Log =>              "log",
+            EventType::Holders =>          "holders",
+        }
+    }
+}
+
 #[derive(ActixMessage, Deserialize, Serialize)]
 #[rtype(result = "()")]
 pub struct MessageToClient {
-    pub types: String,
+    pub types: EventType,
     pub id:    i32,
     pub data:  Value,
 }
 
 impl MessageToClient {
-    pub fn new(types: &str, id: i32, data: Value) -> Self {
+    pub fn new(types: EventType, id: i32, data: Value) -> Self {
         Self {
-            types: types.to_string(),
+            types: types,
             id:    id,
             data,
         }
     }
 }
 
+/// Who a message is delivered to. `Topic` reaches the sessions that
+/// subscribed to it, see `protocol::Topic`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Audience {
+    User(i32),
+    Admins,
+    All,
+    Topic(String),
+}
+
+struct Session {
+    addr:     Recipient<Message>,
+    close:    Recipient<Close>,
+    user_id:  i32,
+    is_admin: bool,
+    topics:   HashSet<String>,
+}
+
 pub struct Server {
-    sessions: HashMap<String, Recipient<Message>>
+    sessions: HashMap<String, Session>,
+    /// session ids of every connected user; a user may have several tabs open
+    users:    HashMap<i32, HashSet<String>>,
+    /// session ids subscribed to each topic
+    topics:   HashMap<String, HashSet<String>>,
 }
 
 impl Server {
     pub fn new() -> Self {
         Server {
             sessions: HashMap::new(),
+            users:    HashMap::new(),
+            topics:   HashMap::new(),
         }
     }
 
-    fn send_message(&self, data: SerdeResult<String>) {
-        match data {
-            Ok(data) => {
-                for recipient in self.sessions.values() {
-                    match recipient.try_send(Message(data.clone())) {
-                        Err(err) => {}
-                        _ => {}
-                    }
-                }
+    fn sessions_of<'a>(&'a self, ids: Option<&'a HashSet<String>>) -> Vec<&'a Session> {
+        ids.map(|ids| ids.iter().filter_map(|id| self.sessions.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    fn send_message(&self, audience: &Audience, message: &MessageToClient) {
+        let topic = match audience {
+            Audience::Topic(topic) => Some(topic.as_str()),
+            _ => None,
+        };
+        let data = match to_string(&ServerFrame::Event { topic: topic, event: message }) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("websocket message error: {}", err);
+                return;
             }
-            Err(err) => {}
+        };
+        let sessions: Vec<&Session> = match audience {
+            Audience::User(user_id) => self.sessions_of(self.users.get(user_id)),
+            Audience::Admins => self.sessions.values().filter(|s| s.is_admin).collect(),
+            Audience::All => self.sessions.values().collect(),
+            Audience::Topic(topic) => self.sessions_of(self.topics.get(topic)),
+        };
+        for session in sessions {
+            // a full mailbox means the client is not reading; the heartbeat
+            // will drop it
+            let _ = session.addr.try_send(Message(data.clone()));
         }
     }
 }
@@ -59,15 +145,28 @@ impl Actor for Server {
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct Connect {
-    pub addr: Recipient<Message>,
-    pub id: String,
+    pub addr:     Recipient<Message>,
+    pub close:    Recipient<Close>,
+    pub id:       String,
+    pub user_id:  i32,
+    pub is_admin: bool,
 }
 
 impl Handler<Connect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
-        self.sessions.insert(msg.id.clone(), msg.addr);
+        self.users
+            .entry(msg.user_id)
+            .or_default()
+            .insert(msg.id.clone());
+        self.sessions.insert(msg.id, Session {
+            addr:     msg.addr,
+            close:    msg.close,
+            user_id:  msg.user_id,
+            is_admin: msg.is_admin,
+            topics:   HashSet::new(),
+        });
     }
 }
 
@@ -81,14 +180,117 @@ impl Handler<Disconnect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        self.sessions.remove(&msg.id);
+        if let Some(session) = self.sessions.remove(&msg.id) {
+            if let Some(ids) = self.users.get_mut(&session.user_id) {
+                ids.remove(&msg.id);
+                if ids.is_empty() {
+                    self.users.remove(&session.user_id);
+                }
+            }
+            for topic in session.topics.iter() {
+                self.leave_topic(topic, &msg.id);
+            }
+        }
     }
 }
 
+/// Closes every session of a user, e.g. once they are blocked or lose
+/// their admin rights. Relayed between instances like `Deliver`.
+#[derive(ActixMessage, Deserialize, Serialize)]
+#[rtype(result = "()")]
+pub struct DisconnectUser {
+    pub user_id: i32,
+}
+
+impl Handler<DisconnectUser> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: DisconnectUser, _: &mut Context<Self>) {
+        // the sessions send `Disconnect` themselves once they stop
+        for session in self.sessions_of(self.users.get(&msg.user_id)) {
+            let _ = session.close.try_send(Close);
+        }
+    }
+}
+
+impl Server {
+    fn leave_topic(&mut self, topic: &str, id: &str) {
+        if let Some(ids) = self.topics.get_mut(topic) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+}
+
+/// Adds session `id` to the followers of `topic`. The session checks the
+/// topic is allowed before sending this.
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Subscribe {
+    pub id:    String,
+    pub topic: String,
+}
+
+impl Handler<Subscribe> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.topics.insert(msg.topic.clone());
+            self.topics.entry(msg.topic).or_default().insert(msg.id);
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Unsubscribe {
+    pub id:    String,
+    pub topic: String,
+}
+
+impl Handler<Unsubscribe> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.topics.remove(&msg.topic);
+        }
+        self.leave_topic(&msg.topic, &msg.id);
+    }
+}
+
+/// Sends to everyone connected.
 impl Handler<MessageToClient> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: MessageToClient, _: &mut Context<Self>) -> Self::Result {
-        self.send_message(to_string(&msg));
+        self.send_message(&Audience::All, &msg);
     }
 }
+
+/// Sends `message` to the sessions of one user, to admins or to everyone.
+#[derive(ActixMessage, Deserialize, Serialize)]
+#[rtype(result = "()")]
+pub struct Deliver {
+    pub audience: Audience,
+    pub message:  MessageToClient,
+}
+
+impl Handler<Deliver> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, _: &mut Context<Self>) -> Self::Result {
+        self.send_message(&msg.audience, &msg.message);
+    }
+}
+
+/// What instances exchange over `LISTEN/NOTIFY`.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Relayed {
+    Deliver(Deliver),
+    DisconnectUser(DisconnectUser),
+}